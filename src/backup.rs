@@ -0,0 +1,238 @@
+// Atomic writes, and timestamped backups of the simfiles itggen changes so that they can be
+// restored to how they were before anything was generated for them.
+//
+// A backup of song.sm is song.sm.<YYYYMMDD-HHMMSS>.itggen.bak, next to it or, with a backup
+// directory, at the same absolute path under that directory. Its metadata is backed up with it.
+
+use crate::metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BACKUP_EXTENSION: &str = "itggen.bak";
+
+// Writes contents to a temporary file next to path and renames it over path, so that path is
+// never left half written. An existing path keeps its permissions.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".itggen.tmp");
+    let tmp = path.with_file_name(name);
+    let result = (|| {
+        let mut f = std::fs::File::create(&tmp)?;
+        io::Write::write_all(&mut f, contents)?;
+        f.sync_all()?;
+        if let Ok(m) = std::fs::metadata(path) {
+            std::fs::set_permissions(&tmp, m.permissions())?;
+        }
+        std::fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+// Returns t as UTC "YYYYMMDD-HHMMSS".
+fn timestamp(t: SystemTime) -> String {
+    let secs = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // civil_from_days from http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn is_timestamp(s: &str) -> bool {
+    s.len() == 15
+        && s.char_indices()
+            .all(|(i, c)| if i == 8 { c == '-' } else { c.is_ascii_digit() })
+}
+
+// Returns where backups of path go, without the timestamp and extension.
+fn backup_base(path: &Path, backup_dir: Option<&Path>) -> io::Result<PathBuf> {
+    let Some(backup_dir) = backup_dir else {
+        return Ok(path.to_owned());
+    };
+    let mut ret = backup_dir.to_owned();
+    for c in std::fs::canonicalize(path)?.components() {
+        if let std::path::Component::Normal(c) = c {
+            ret.push(c);
+        }
+    }
+    Ok(ret)
+}
+
+fn backup_path(base: &Path, timestamp: &str) -> PathBuf {
+    let mut s = base.as_os_str().to_owned();
+    s.push(format!(".{}.{}", timestamp, BACKUP_EXTENSION));
+    PathBuf::from(s)
+}
+
+// Returns where the backup of path's metadata with the given timestamp goes.
+fn metadata_backup_path(
+    path: &Path,
+    backup_dir: Option<&Path>,
+    timestamp: &str,
+) -> io::Result<PathBuf> {
+    let metadata_path = metadata::path_for(path);
+    let base = backup_base(path, backup_dir)?
+        .with_file_name(metadata_path.file_name().unwrap_or_default());
+    Ok(backup_path(&base, timestamp))
+}
+
+// Returns the timestamps of the backups of path, oldest first.
+fn backup_timestamps(path: &Path, backup_dir: Option<&Path>) -> io::Result<Vec<String>> {
+    let base = backup_base(path, backup_dir)?;
+    let (Some(dir), Some(name)) = (base.parent(), base.file_name()) else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{}.", name.to_string_lossy());
+    let suffix = format!(".{}", BACKUP_EXTENSION);
+    let Ok(rd) = std::fs::read_dir(dir) else {
+        return Ok(Vec::new());
+    };
+    let mut ret = Vec::new();
+    for de in rd {
+        let name = de?.file_name().to_string_lossy().into_owned();
+        if let Some(t) = name
+            .strip_prefix(&prefix)
+            .and_then(|s| s.strip_suffix(&suffix))
+            && is_timestamp(t)
+        {
+            ret.push(t.to_owned());
+        }
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+// Copies the simfile at path and its metadata to a new backup, returning the backup's path. If
+// there already is a backup from this second, it is kept as is.
+pub fn backup(path: &Path, backup_dir: Option<&Path>) -> io::Result<PathBuf> {
+    let timestamp = timestamp(SystemTime::now());
+    let dst = backup_path(&backup_base(path, backup_dir)?, &timestamp);
+    if dst.exists() {
+        return Ok(dst);
+    }
+    if let Some(dir) = dst.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let metadata_path = metadata::path_for(path);
+    if metadata_path.is_file() {
+        std::fs::copy(
+            &metadata_path,
+            metadata_backup_path(path, backup_dir, &timestamp)?,
+        )?;
+    }
+    std::fs::copy(path, &dst)?;
+    Ok(dst)
+}
+
+// Puts the simfile at path and its metadata back the way they were in their oldest backup, and
+// returns that backup's path, or None if there is no backup. Backups are kept.
+pub fn restore(
+    path: &Path,
+    backup_dir: Option<&Path>,
+    dry_run: bool,
+) -> io::Result<Option<PathBuf>> {
+    let Some(timestamp) = backup_timestamps(path, backup_dir)?.into_iter().next() else {
+        return Ok(None);
+    };
+    let src = backup_path(&backup_base(path, backup_dir)?, &timestamp);
+    if dry_run {
+        return Ok(Some(src));
+    }
+    let metadata_path = metadata::path_for(path);
+    let metadata_src = metadata_backup_path(path, backup_dir, &timestamp)?;
+    if metadata_src.is_file() {
+        write_atomic(&metadata_path, &std::fs::read(metadata_src)?)?;
+    } else if metadata_path.is_file() {
+        std::fs::remove_file(&metadata_path)?;
+    }
+    write_atomic(path, &std::fs::read(&src)?)?;
+    Ok(Some(src))
+}
+
+#[test]
+fn test_timestamp() {
+    let t = |secs| timestamp(UNIX_EPOCH + std::time::Duration::from_secs(secs));
+    assert_eq!(t(0), "19700101-000000");
+    assert_eq!(t(951782400 + 3661), "20000229-010101");
+    assert_eq!(t(1792108800), "20261016-000000");
+    assert!(is_timestamp(&t(1792108800)));
+    assert!(!is_timestamp("itggen.json.2026"));
+}
+
+#[test]
+fn test_backup_restore() {
+    let dir = std::env::temp_dir().join(format!("itggen-backup-{}", std::process::id()));
+    let song_dir = dir.join("pack/song");
+    std::fs::create_dir_all(&song_dir).unwrap();
+    let song = song_dir.join("song.sm");
+    write_atomic(&song, b"original").unwrap();
+    assert_eq!(std::fs::read(&song).unwrap(), b"original");
+    assert_eq!(restore(&song, None, false).unwrap(), None);
+
+    for backup_dir in [None, Some(dir.join("backups"))] {
+        let backup_dir = backup_dir.as_deref();
+        let b = backup(&song, backup_dir).unwrap();
+        assert_eq!(std::fs::read(&b).unwrap(), b"original");
+        write_atomic(&song, b"generated").unwrap();
+        std::fs::write(metadata::path_for(&song), "[]").unwrap();
+        assert_eq!(backup_timestamps(&song, backup_dir).unwrap().len(), 1);
+
+        assert_eq!(restore(&song, backup_dir, true).unwrap(), Some(b.clone()));
+        assert_eq!(std::fs::read(&song).unwrap(), b"generated");
+        assert_eq!(restore(&song, backup_dir, false).unwrap(), Some(b.clone()));
+        assert_eq!(std::fs::read(&song).unwrap(), b"original");
+        assert!(!metadata::path_for(&song).exists());
+        std::fs::remove_file(b).unwrap();
+    }
+    std::fs::write(metadata::path_for(&song), "[1]").unwrap();
+    backup(&song, None).unwrap();
+    std::fs::write(metadata::path_for(&song), "[2]").unwrap();
+    restore(&song, None, false).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(metadata::path_for(&song)).unwrap(),
+        "[1]"
+    );
+    assert!(
+        dir.join("backups")
+            .join(
+                std::fs::canonicalize(&song_dir)
+                    .unwrap()
+                    .strip_prefix("/")
+                    .unwrap()
+            )
+            .is_dir()
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        std::fs::set_permissions(&song, std::fs::Permissions::from_mode(0o640)).unwrap();
+        write_atomic(&song, b"generated").unwrap();
+        assert_eq!(mode(&song), 0o640);
+        std::fs::set_permissions(&song, std::fs::Permissions::from_mode(0o444)).unwrap();
+        write_atomic(&song, b"again").unwrap();
+        assert_eq!(mode(&song), 0o444);
+        assert_eq!(std::fs::read(&song).unwrap(), b"again");
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
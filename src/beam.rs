@@ -0,0 +1,138 @@
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+// How many times each state is extended per row.
+const BRANCHING: usize = 4;
+// How many rows to back up by (times the number of dead ends so far before the same row) after
+// a dead end.
+const BACKTRACK_ROWS: usize = 4;
+// How many dead ends there can be before getting past a row.
+const MAX_BACKTRACKS: usize = 16;
+
+struct Node<S> {
+    state: S,
+    parent: usize,
+    output: String,
+}
+
+// Beam search over rows. Every row, each of the width best states so far is extended
+// BRANCHING times by step() with a different seed, and the width best results by score()
+// are kept. States that step() can't extend are dropped. If none can be extended, the
+// search backs up a few rows and tries again with new seeds, backing up further every time
+// until it gets past the furthest row it got stuck on. All seeds are derived from seed, so
+// the result is reproducible.
+//
+// Returns the output of step() for every row of the best final state, and that state, or the
+// index of the row it couldn't get past.
+pub fn beam_search<S: Clone, R>(
+    init: S,
+    rows: &[R],
+    width: usize,
+    seed: u64,
+    mut step: impl FnMut(&mut S, &R, u64) -> Option<String>,
+    score: impl Fn(&S) -> f32,
+) -> Result<(Vec<String>, S), usize> {
+    let mut rand = ChaCha8Rng::seed_from_u64(seed);
+    let mut layers = vec![vec![Node {
+        state: init,
+        parent: 0,
+        output: String::new(),
+    }]];
+    // the furthest row the search got stuck on since it last got past one, and how many dead
+    // ends there have been since
+    let mut stuck_row = None;
+    let mut backtracks = 0;
+    while layers.len() <= rows.len() {
+        let row_idx = layers.len() - 1;
+        let mut children: Vec<Node<S>> = Vec::new();
+        for (parent, node) in layers.last().unwrap().iter().enumerate() {
+            for _ in 0..BRANCHING {
+                let mut state = node.state.clone();
+                if let Some(output) = step(&mut state, &rows[row_idx], rand.random())
+                    && !children
+                        .iter()
+                        .any(|c| c.parent == parent && c.output == output)
+                {
+                    children.push(Node {
+                        state,
+                        parent,
+                        output,
+                    });
+                }
+            }
+        }
+        if children.is_empty() {
+            stuck_row = Some(stuck_row.map_or(row_idx, |r: usize| r.max(row_idx)));
+            backtracks += 1;
+            if backtracks > MAX_BACKTRACKS {
+                return Err(row_idx);
+            }
+            layers.truncate(layers.len() - (BACKTRACK_ROWS * backtracks).min(row_idx));
+            continue;
+        }
+        children.sort_by(|a, b| score(&b.state).total_cmp(&score(&a.state)));
+        children.truncate(width.max(1));
+        layers.push(children);
+        if stuck_row == Some(row_idx) {
+            stuck_row = None;
+            backtracks = 0;
+        }
+    }
+
+    let mut ret = Vec::new();
+    let mut idx = 0;
+    for layer in layers.iter().skip(1).rev() {
+        ret.push(layer[idx].output.clone());
+        idx = layer[idx].parent;
+    }
+    ret.reverse();
+    let best = layers.pop().unwrap().swap_remove(0).state;
+    Ok((ret, best))
+}
+
+#[test]
+fn test_beam_search() {
+    // digits 0-3 with the highest sum, except that row 5 can only follow a 3
+    let step = |state: &mut Vec<u64>, row: &usize, seed: u64| {
+        if *row == 5 && state.last() != Some(&3) {
+            return None;
+        }
+        state.push(seed % 4);
+        Some((seed % 4).to_string())
+    };
+    let score = |state: &Vec<u64>| state.iter().sum::<u64>() as f32;
+    let rows: Vec<usize> = (0..10).collect();
+    let (out, state) = beam_search(Vec::new(), &rows, 1, 7, step, score).unwrap();
+    assert_eq!(out.len(), 10);
+    assert_eq!(state.len(), 10);
+    assert_eq!(state[4], 3);
+    assert_eq!(out, state.iter().map(|d| d.to_string()).collect::<Vec<_>>());
+    assert_eq!(
+        beam_search(Vec::new(), &rows, 1, 7, step, score).unwrap(),
+        (out, state)
+    );
+
+    let (out, _) = beam_search(Vec::new(), &rows, 8, 7, step, score).unwrap();
+    assert_eq!(out.len(), 10);
+
+    let (out, state) = beam_search(Vec::new(), &[] as &[usize], 8, 7, step, score).unwrap();
+    assert!(out.is_empty());
+    assert!(state.is_empty());
+
+    // separate dead ends all through a long chart, which only fail together if dead ends are
+    // counted for the whole chart
+    let step = |state: &mut Vec<u64>, row: &usize, seed: u64| {
+        if *row % 10 == 9 && !state.ends_with(&[3, 3]) {
+            return None;
+        }
+        state.push(seed % 4);
+        Some((seed % 4).to_string())
+    };
+    let rows: Vec<usize> = (0..200).collect();
+    let (_, state) = beam_search(Vec::new(), &rows, 1, 7, step, score).unwrap();
+    assert_eq!(state.len(), 200);
+    assert!((0..20).all(|i| state[i * 10 + 7..i * 10 + 9] == [3, 3]));
+
+    let dead_end = |_: &mut Vec<u64>, row: &usize, _: u64| (*row < 3).then(String::new);
+    assert!(beam_search(Vec::new(), &rows, 4, 7, dead_end, score).is_err());
+}
@@ -0,0 +1,75 @@
+// What writing generated charts would change in a simfile, shown instead on dry runs.
+
+use crate::error::Error;
+use crate::sm::{self, SMChart};
+use similar::TextDiff;
+
+// Returns a unified diff from old to new of the file at path.
+pub fn unified(path: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(path, path)
+        .to_string()
+}
+
+fn same_chart(a: &SMChart, b: &SMChart) -> bool {
+    a.style == b.style
+        && a.description == b.description
+        && a.difficulty == b.difficulty
+        && a.level == b.level
+        && a.notes_lines == b.notes_lines
+}
+
+// Returns "- chart" for every chart in old that isn't in new and "+ chart" for every chart in
+// new that isn't in old.
+pub fn chart_changes(old: &str, new: &str, is_ssc: bool) -> Result<Vec<String>, Error> {
+    let old = sm::parse_charts(old, is_ssc)?;
+    let mut added = sm::parse_charts(new, is_ssc)?;
+    let mut ret = Vec::new();
+    for c in &old {
+        match added.iter().position(|n| same_chart(c, n)) {
+            Some(i) => {
+                added.remove(i);
+            }
+            None => ret.push(format!("- {}", c.summary())),
+        }
+    }
+    ret.extend(added.iter().map(|c| format!("+ {}", c.summary())));
+    Ok(ret)
+}
+
+#[test]
+fn test_chart_changes() {
+    let chart = |description: &str, notes: &str| {
+        format!(
+            "#NOTES:\n     dance-single:\n     {}:\n     Hard:\n     9:\n     :\n{}\n;\n",
+            description, notes
+        )
+    };
+    let old = format!(
+        "#TITLE:a;\n{}{}",
+        chart("a", "1000"),
+        chart("AYEAG", "0100")
+    );
+    let new = format!(
+        "#TITLE:a;\n{}{}",
+        chart("a", "1000"),
+        chart("AYEAG", "0010")
+    );
+    assert_eq!(
+        chart_changes(&old, &new, false).unwrap(),
+        vec![
+            "- dance-single Hard 9 'AYEAG'".to_owned(),
+            "+ dance-single Hard 9 'AYEAG'".to_owned()
+        ]
+    );
+    assert!(chart_changes(&old, &old, false).unwrap().is_empty());
+    assert!(chart_changes(&old, "#NOTES:\n", false).is_err());
+
+    assert_eq!(
+        unified("a.sm", &old, &new),
+        "--- a.sm\n+++ a.sm\n@@ -13,5 +13,5 @@\n      Hard:\n      9:\n      :\n-0100\n+0010\n ;\n"
+    );
+    assert_eq!(unified("a.sm", &old, &old), "");
+}
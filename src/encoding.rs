@@ -0,0 +1,106 @@
+use crate::backup;
+use crate::error::Error;
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Encoding, UTF_8};
+use std::path::Path;
+
+// Returns the encoding for a label like "shift_jis", "cp1252" or "utf-8".
+pub fn from_label(label: &str) -> Result<&'static Encoding, Error> {
+    let encoding = Encoding::for_label(label.as_bytes())
+        .ok_or(Error::Encoding(format!("unknown encoding '{}'", label)))?;
+    // e.g. UTF-16, which encoding_rs can only decode
+    if encoding.output_encoding() != encoding {
+        return Err(Error::Encoding(format!(
+            "can't write {} files",
+            encoding.name()
+        )));
+    }
+    Ok(encoding)
+}
+
+// Returns bytes decoded as encoding, or as the encoding they look like if it is None, with the
+// encoding used.
+//
+// Files that aren't UTF-8 are only decoded if encoding the text again gives back the same bytes,
+// so that everything in them but the generated charts is written back unchanged.
+pub fn decode(
+    bytes: &[u8],
+    encoding: Option<&'static Encoding>,
+) -> Result<(String, &'static Encoding), Error> {
+    let encoding = encoding.unwrap_or_else(|| {
+        if std::str::from_utf8(bytes).is_ok() {
+            return UTF_8;
+        }
+        let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+        detector.feed(bytes, true);
+        detector.guess(None, Utf8Detection::Deny)
+    });
+    // the BOM stays in the text so that it is written back
+    let text = encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .ok_or(Error::Encoding(format!("not valid {}", encoding.name())))?
+        .into_owned();
+    if encode(&text, encoding)? != bytes {
+        return Err(Error::Encoding(format!(
+            "would change when written back as {}",
+            encoding.name()
+        )));
+    }
+    Ok((text, encoding))
+}
+
+pub fn encode(text: &str, encoding: &'static Encoding) -> Result<Vec<u8>, Error> {
+    let (bytes, _, had_errors) = encoding.encode(text);
+    if had_errors {
+        return Err(Error::Encoding(format!(
+            "generated text can't be written as {}",
+            encoding.name()
+        )));
+    }
+    Ok(bytes.into_owned())
+}
+
+pub fn read(
+    path: &Path,
+    encoding: Option<&'static Encoding>,
+) -> Result<(String, &'static Encoding), Error> {
+    decode(&std::fs::read(path)?, encoding)
+}
+
+pub fn write(path: &Path, text: &str, encoding: &'static Encoding) -> Result<(), Error> {
+    Ok(backup::write_atomic(path, &encode(text, encoding)?)?)
+}
+
+#[test]
+fn test_decode() {
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+    let utf8 = "\u{feff}#TITLE:夜桜;\n".as_bytes();
+    assert_eq!(
+        decode(utf8, None).unwrap(),
+        ("\u{feff}#TITLE:夜桜;\n".to_owned(), UTF_8)
+    );
+
+    let (sjis, _, _) = SHIFT_JIS.encode("#TITLE:夜桜お七;\n#ARTIST:乙女のポリシー;\n");
+    let (text, encoding) = decode(&sjis, None).unwrap();
+    assert_eq!(encoding, SHIFT_JIS);
+    assert_eq!(text, "#TITLE:夜桜お七;\n#ARTIST:乙女のポリシー;\n");
+    let appended = text + "#NOTES:\n     dance-double:\n";
+    assert!(encode(&appended, encoding).unwrap().starts_with(&sjis));
+    assert!(encode("#TITLE:★;", WINDOWS_1252).is_err());
+
+    let cp1252 = b"#TITLE:Caf\xe9 Ol\xe9 (Pok\xe9mon Remix);\n#ARTIST:Ren\xe9e;\n";
+    assert_eq!(decode(cp1252, None).unwrap().1, WINDOWS_1252);
+    assert_eq!(
+        decode(cp1252, Some(from_label("latin1").unwrap()))
+            .unwrap()
+            .0,
+        "#TITLE:Café Olé (Pokémon Remix);\n#ARTIST:Renée;\n"
+    );
+    assert!(decode(cp1252, Some(UTF_8)).is_err());
+
+    // NEC's "≒" decodes to the same character as JIS's, which is what it's encoded back as
+    assert!(decode(b"#TITLE:\x87\x90;", Some(SHIFT_JIS)).is_err());
+
+    assert!(from_label("utf-16le").is_err());
+    assert!(from_label("klingon").is_err());
+}
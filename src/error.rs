@@ -0,0 +1,115 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    // fewer than the 5 metadata fields after an .sm #NOTES:
+    MissingMetadata,
+    // a tag that is never closed by ';'
+    MissingSemicolon(String),
+    // a required .ssc tag that isn't there
+    MissingTag(String),
+    // a meter that isn't a number
+    BadMeter(String),
+    UnknownNote(char),
+    BadTiming(String),
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::MissingMetadata => {
+                f.write_str("expected 5 metadata fields after #NOTES:")
+            }
+            ParseErrorKind::MissingSemicolon(tag) => write!(f, "couldn't find ';' after #{}", tag),
+            ParseErrorKind::MissingTag(tag) => write!(f, "missing #{}", tag),
+            ParseErrorKind::BadMeter(s) => write!(f, "couldn't parse meter '{}'", s),
+            ParseErrorKind::UnknownNote(c) => write!(f, "unknown note '{}'", c),
+            ParseErrorKind::BadTiming(s) => write!(f, "couldn't parse timing: {}", s),
+        }
+    }
+}
+
+// Where in an .sm/.ssc file parsing failed, and why.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    // 1-based line number
+    pub line: Option<usize>,
+    // 0-based index of the chart in the file
+    pub chart: Option<usize>,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, line: usize) -> Self {
+        Self {
+            kind,
+            line: Some(line),
+            chart: None,
+        }
+    }
+
+    pub fn in_chart(self, chart: usize) -> Self {
+        Self {
+            chart: Some(chart),
+            ..self
+        }
+    }
+}
+
+// e.g. "line 12: unknown note 'x' (chart 2)"
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        write!(f, "{}", self.kind)?;
+        if let Some(chart) = self.chart {
+            write!(f, " (chart {})", chart + 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    // an .sm/.ssc file couldn't be parsed
+    Parse(ParseError),
+    // a preset, curve, itggen.toml or metadata file is invalid
+    Config(String),
+    // a chart couldn't be generated, e.g. because there were no valid steps
+    Generate(String),
+    // a simfile that can't be decoded, or written back in its encoding
+    Encoding(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::Config(s) | Error::Generate(s) | Error::Encoding(s) => f.write_str(s),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::Parse(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
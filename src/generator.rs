@@ -1,10 +1,174 @@
 use crate::foot::Foot;
 use crate::style::Style;
 use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
 use std::fmt::Debug;
+use std::str::FromStr;
 
-#[derive(Copy, Clone, Default, Debug)]
+// What to do with the notes of hands and quads beyond the two that the feet take.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HandsPolicy {
+    // drop the extra notes, leaving a jump
+    #[default]
+    Jumps,
+    // put each extra note on a panel next to a different foot, dropping what doesn't fit
+    Brackets,
+    // put the extra notes on any free panels
+    Hands,
+}
+
+#[derive(Debug)]
+pub struct HandsPolicyParseError(String);
+
+impl FromStr for HandsPolicy {
+    type Err = HandsPolicyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jumps" => Ok(HandsPolicy::Jumps),
+            "brackets" => Ok(HandsPolicy::Brackets),
+            "hands" => Ok(HandsPolicy::Hands),
+            _ => Err(HandsPolicyParseError(s.to_owned())),
+        }
+    }
+}
+
+impl std::fmt::Display for HandsPolicyParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not parse hands policy '{}'", self.0)
+    }
+}
+
+impl std::fmt::Display for HandsPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HandsPolicy::Jumps => "jumps",
+            HandsPolicy::Brackets => "brackets",
+            HandsPolicy::Hands => "hands",
+        })
+    }
+}
+
+// Limits on where a foot can step, in the order they are relaxed when no column satisfies
+// all of them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Rule {
+    MaxHorizontalDistBetween4StepsBothFeet,
+    MaxHorizontalDistBetween3StepsSameFoot,
+    MaxBarAngle,
+    MaxVerticalDistBetweenSteps,
+    MaxHorizontalDistBetweenStepsIfCrossover,
+    MaxHorizontalDistBetweenSteps,
+    TimedMaxHorizontalDistBetweenSteps,
+    TimedMaxDistBetweenSteps,
+    MaxRepeated,
+    MaxTurn,
+    MaxAngle,
+    DisallowFootOppositeSide,
+    MaxDistBetweenSteps,
+    MaxDistBetweenFeetIfCrossover,
+    MaxDistBetweenFeet,
+    DisallowFootswitch,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 16] = [
+        Rule::MaxHorizontalDistBetween4StepsBothFeet,
+        Rule::MaxHorizontalDistBetween3StepsSameFoot,
+        Rule::MaxBarAngle,
+        Rule::MaxVerticalDistBetweenSteps,
+        Rule::MaxHorizontalDistBetweenStepsIfCrossover,
+        Rule::MaxHorizontalDistBetweenSteps,
+        Rule::TimedMaxHorizontalDistBetweenSteps,
+        Rule::TimedMaxDistBetweenSteps,
+        Rule::MaxRepeated,
+        Rule::MaxTurn,
+        Rule::MaxAngle,
+        Rule::DisallowFootOppositeSide,
+        Rule::MaxDistBetweenSteps,
+        Rule::MaxDistBetweenFeetIfCrossover,
+        Rule::MaxDistBetweenFeet,
+        Rule::DisallowFootswitch,
+    ];
+
+    // The name of the GeneratorParameters field that sets this rule.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::MaxHorizontalDistBetween4StepsBothFeet => {
+                "max_horizontal_dist_between_4_steps_both_feet"
+            }
+            Rule::MaxHorizontalDistBetween3StepsSameFoot => {
+                "max_horizontal_dist_between_3_steps_same_foot"
+            }
+            Rule::MaxBarAngle => "max_bar_angle",
+            Rule::MaxVerticalDistBetweenSteps => "max_vertical_dist_between_steps",
+            Rule::MaxHorizontalDistBetweenStepsIfCrossover => {
+                "max_horizontal_dist_between_steps_if_crossover"
+            }
+            Rule::MaxHorizontalDistBetweenSteps => "max_horizontal_dist_between_steps",
+            Rule::TimedMaxHorizontalDistBetweenSteps => "timed_max_horizontal_dist_between_steps",
+            Rule::TimedMaxDistBetweenSteps => "timed_max_dist_between_steps",
+            Rule::MaxRepeated => "max_repeated",
+            Rule::MaxTurn => "max_turn",
+            Rule::MaxAngle => "max_angle",
+            Rule::DisallowFootOppositeSide => "disallow_foot_opposite_side",
+            Rule::MaxDistBetweenSteps => "max_dist_between_steps",
+            Rule::MaxDistBetweenFeetIfCrossover => "max_dist_between_feet_if_crossover",
+            Rule::MaxDistBetweenFeet => "max_dist_between_feet",
+            Rule::DisallowFootswitch => "disallow_footswitch",
+        }
+    }
+
+    fn is_set(&self, params: &GeneratorParameters) -> bool {
+        match self {
+            Rule::MaxHorizontalDistBetween4StepsBothFeet => params
+                .max_horizontal_dist_between_4_steps_both_feet
+                .is_some(),
+            Rule::MaxHorizontalDistBetween3StepsSameFoot => params
+                .max_horizontal_dist_between_3_steps_same_foot
+                .is_some(),
+            Rule::MaxBarAngle => params.max_bar_angle.is_some(),
+            Rule::MaxVerticalDistBetweenSteps => params.max_vertical_dist_between_steps.is_some(),
+            Rule::MaxHorizontalDistBetweenStepsIfCrossover => params
+                .max_horizontal_dist_between_steps_if_crossover
+                .is_some(),
+            Rule::MaxHorizontalDistBetweenSteps => {
+                params.max_horizontal_dist_between_steps.is_some()
+            }
+            Rule::TimedMaxHorizontalDistBetweenSteps => {
+                params.timed_max_horizontal_dist_between_steps.is_some()
+            }
+            Rule::TimedMaxDistBetweenSteps => params.timed_max_dist_between_steps.is_some(),
+            Rule::MaxRepeated => params.max_repeated.is_some(),
+            Rule::MaxTurn => params.max_turn.is_some(),
+            Rule::MaxAngle => params.max_angle.is_some(),
+            Rule::DisallowFootOppositeSide => params.disallow_foot_opposite_side,
+            Rule::MaxDistBetweenSteps => params.max_dist_between_steps.is_some(),
+            Rule::MaxDistBetweenFeetIfCrossover => {
+                params.max_dist_between_feet_if_crossover.is_some()
+            }
+            Rule::MaxDistBetweenFeet => params.max_dist_between_feet.is_some(),
+            Rule::DisallowFootswitch => params.disallow_footswitch,
+        }
+    }
+
+    fn is_enforced(&self, relaxed: usize) -> bool {
+        *self as usize >= relaxed
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Fields missing from a serialized GeneratorParameters take their default values.
+#[derive(Copy, Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GeneratorParameters {
     pub seed: Option<u64>,
     pub disallow_footswitch: bool,
@@ -21,6 +185,10 @@ pub struct GeneratorParameters {
     pub max_horizontal_dist_between_steps_if_crossover: Option<f32>,
     pub max_vertical_dist_between_steps: Option<f32>,
     pub vertical_dist_between_steps_decay: Option<(f32, f32)>,
+    // (dist, dist per second): a foot can move at most dist plus dist per second for every
+    // second since it last stepped
+    pub timed_max_dist_between_steps: Option<(f32, f32)>,
+    pub timed_max_horizontal_dist_between_steps: Option<(f32, f32)>,
     pub horizontal_dist_between_3_steps_same_foot_decay: Option<(f32, f32)>,
     pub max_horizontal_dist_between_4_steps_both_feet: Option<f32>,
     pub max_horizontal_dist_between_3_steps_same_foot: Option<f32>,
@@ -36,8 +204,12 @@ pub struct GeneratorParameters {
     pub doubles_dist_from_side: Option<f32>,
     pub doubles_steps_per_dist: Option<f32>,
     pub doubles_track_individual_feet: bool,
+    // keep this many candidate charts and pick the most likely one instead of choosing each
+    // step independently
+    pub beam_width: Option<usize>,
     pub disallow_foot_opposite_side: bool,
     pub remove_jumps: bool,
+    pub hands: HandsPolicy,
     pub min_difficulty: Option<i32>,
     pub max_difficulty: Option<i32>,
 }
@@ -48,6 +220,15 @@ struct FootStatus {
     pub last_col: Option<i8>,
     pub repeated: i32,
     pub last_input_col: Option<i8>,
+    pub last_time: Option<f32>,
+    // the column of the hold or roll the foot is on, until release() of that column
+    pub held_col: Option<i8>,
+}
+
+impl FootStatus {
+    fn holding(&self) -> bool {
+        self.held_col.is_some()
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -100,14 +281,23 @@ fn test_zone() {
     assert_eq!(z.current_x(), 7.0);
 }
 
+#[derive(Clone)]
 pub struct Generator {
     style: Style,
     params: GeneratorParameters,
-    rand: StdRng,
+    rand: ChaCha8Rng,
     feet_status: [FootStatus; 2],
     next_foot: Foot,
     prev_angle: f32,
     zone: Zone,
+    // seconds into the song of the step being generated, if the chart's timing is known
+    time: Option<f32>,
+    // columns held down by hands or brackets rather than by a foot
+    blocked: Vec<i8>,
+    // sum of the log probabilities of every randomly chosen column so far
+    log_prob: f32,
+    // rules relaxed since the last take_relaxations()
+    relaxations: Vec<Rule>,
 }
 
 impl Debug for Generator {
@@ -119,6 +309,10 @@ impl Debug for Generator {
             .field("next_foot", &self.next_foot)
             .field("prev_angle", &self.prev_angle)
             .field("zone", &self.zone)
+            .field("time", &self.time)
+            .field("blocked", &self.blocked)
+            .field("log_prob", &self.log_prob)
+            .field("relaxations", &self.relaxations)
             .finish()
     }
 }
@@ -127,8 +321,8 @@ impl Generator {
     pub fn new(style: Style, params: GeneratorParameters) -> Self {
         let mut rand = params
             .seed
-            .map(StdRng::seed_from_u64)
-            .unwrap_or_else(StdRng::from_os_rng);
+            .map(ChaCha8Rng::seed_from_u64)
+            .unwrap_or_else(ChaCha8Rng::from_os_rng);
         let next_foot = if rand.random() {
             Foot::Left
         } else {
@@ -148,6 +342,10 @@ impl Generator {
             next_foot,
             prev_angle: 0.0,
             zone,
+            time: None,
+            blocked: Vec::new(),
+            log_prob: 0.0,
+            relaxations: Vec::new(),
         }
     }
 }
@@ -158,74 +356,118 @@ impl Generator {
         self.generate_with_input_col(-1, false)
     }
 
+    #[cfg(test)]
     pub fn generate_with_input_col(&mut self, input_col: i8, is_jump: bool) -> i8 {
+        match self.try_generate_with_input_col(input_col, is_jump) {
+            Some(col) => col,
+            None => panic!("no available columns! {:?}", self),
+        }
+    }
+
+    // Like generate_with_input_col(), but returns None when there is no column to step on even
+    // after relaxing every rule.
+    pub fn try_generate_with_input_col(&mut self, input_col: i8, is_jump: bool) -> Option<i8> {
+        if self.next_foot_status().holding() && !self.prev_foot_status().holding() {
+            // the next foot is pinned on a hold, so the free foot takes this step
+            self.next_foot = self.next_foot.other();
+        }
         if self.params.preserve_input_repetitions.is_some() || is_jump {
             if self.next_foot_status().last_input_col == Some(input_col) {
-                if let Some(lc) = self.next_foot_status().last_col {
+                if let Some(lc) = self.next_foot_status().last_col
+                    && !self.next_foot_status().holding()
+                {
                     self.step_with_input_col(lc, input_col);
-                    return lc;
-                }
-            } else if self.prev_foot_status().last_input_col == Some(input_col) {
-                if let Some(lc) = self.prev_foot_status().last_col {
-                    self.step_without_switching_feet(lc, input_col);
-                    return lc;
+                    return Some(lc);
                 }
+            } else if self.prev_foot_status().last_input_col == Some(input_col)
+                && !self.prev_foot_status().holding()
+                && let Some(lc) = self.prev_foot_status().last_col
+            {
+                self.step_without_switching_feet(lc, input_col);
+                return Some(lc);
             }
         }
         self.gen_impl(input_col)
     }
 
-    fn gen_impl(&mut self, input_col: i8) -> i8 {
+    fn gen_impl(&mut self, input_col: i8) -> Option<i8> {
         let col = if self.next_foot_status().last_col.is_none() {
             self.style.init_col(self.next_foot)
         } else {
-            self.choose(input_col)
+            self.choose(input_col)?
         };
         self.step_impl(col, input_col, true);
-        col
+        Some(col)
     }
 
-    fn choose(&mut self, input_col: i8) -> i8 {
-        let col_probs: Vec<(i8, f32)> = self
-            .valid_cols()
-            .into_iter()
-            .map(|c| (c, self.prob_with_input_col(c, input_col)))
-            .collect();
-        self.choose_from_probs(col_probs)
+    // Rules are relaxed while no column passes them, or every one that does has probability 0.0.
+    fn choose(&mut self, input_col: i8) -> Option<i8> {
+        let mut relaxed = 0;
+        let mut cols = self.valid_cols();
+        loop {
+            let col_probs: Vec<(i8, f32)> = cols
+                .iter()
+                .map(|c| (*c, self.prob_with_input_col(*c, input_col)))
+                .collect();
+            if relaxed == Rule::ALL.len() || col_probs.iter().any(|(_, p)| *p > 0.0) {
+                return self.choose_from_probs(col_probs);
+            }
+            let rule = Rule::ALL[relaxed];
+            relaxed += 1;
+            if !rule.is_set(&self.params) {
+                continue;
+            }
+            self.relaxations.push(rule);
+            self.log_prob -= Self::RELAXATION_PENALTY;
+            cols = (0..(self.style.num_cols()))
+                .filter(|c| self.is_valid_col_with_relaxed(*c, relaxed))
+                .collect();
+        }
     }
 
     fn valid_cols(&self) -> Vec<i8> {
-        let cols: Vec<i8> = (0..(self.style.num_cols()))
+        (0..(self.style.num_cols()))
             .filter(|c| self.is_valid_col(*c))
-            .collect();
-        if cols.is_empty() {
-            println!("{:?}", self);
-            panic!("no available columns!");
-        }
-        cols
+            .collect()
     }
 
-    fn choose_from_probs(&mut self, col_probs: Vec<(i8, f32)>) -> i8 {
+    // Returns None if there are no columns or they all have probability 0.0.
+    fn choose_from_probs(&mut self, col_probs: Vec<(i8, f32)>) -> Option<i8> {
         let total_prob: f32 = col_probs.iter().map(|(_, p)| p).sum();
         if total_prob == 0.0 {
-            println!("{:?}", self);
-            panic!("All valid columns have probability 0.0");
+            return None;
         }
         let prob_remaining = self.rand.random_range(0.0..total_prob);
-        Self::choose_from_probs_with_prob(col_probs, prob_remaining)
+        let (col, prob) = Self::choose_from_probs_with_prob(&col_probs, prob_remaining);
+        self.log_prob += (prob / total_prob).ln();
+        Some(col)
     }
 
-    fn choose_from_probs_with_prob(col_probs: Vec<(i8, f32)>, mut prob: f32) -> i8 {
+    fn choose_from_probs_with_prob(col_probs: &[(i8, f32)], mut prob: f32) -> (i8, f32) {
         if prob <= 0.0 && col_probs.is_empty() {
             panic!("invalid arguments: {:?} {:?}", prob, col_probs);
         }
-        for (c, p) in &col_probs {
+        for (c, p) in col_probs {
             prob -= p;
             if prob <= 0.0 {
-                return *c;
+                return (*c, *p);
             }
         }
-        col_probs.last().unwrap().0
+        *col_probs.last().unwrap()
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.rand = ChaCha8Rng::seed_from_u64(seed);
+    }
+
+    // Returns the rules that had to be relaxed to find a column since the last call.
+    pub fn take_relaxations(&mut self) -> Vec<Rule> {
+        std::mem::take(&mut self.relaxations)
+    }
+
+    // How likely the steps chosen so far were, for comparing generators.
+    pub fn log_prob(&self) -> f32 {
+        self.log_prob
     }
 
     #[cfg(test)]
@@ -242,6 +484,7 @@ impl Generator {
     }
 
     fn step_impl(&mut self, col: i8, input_col: i8, switch_feet: bool) {
+        let time = self.time;
         let foot_status = if switch_feet {
             self.next_foot_status_mut()
         } else {
@@ -257,6 +500,7 @@ impl Generator {
         foot_status.last_last_col = foot_status.last_col;
         foot_status.last_col = Some(col);
         foot_status.last_input_col = Some(input_col);
+        foot_status.last_time = time;
 
         if let Some(a) = self.calc_cur_angle() {
             self.prev_angle = a;
@@ -274,6 +518,86 @@ impl Generator {
         }
     }
 
+    pub fn set_time(&mut self, seconds: f32) {
+        self.time = Some(seconds);
+    }
+
+    // Pins the foot that just stepped on its column until release() is called.
+    pub fn hold_last_step(&mut self) {
+        let foot_status = self.prev_foot_status_mut();
+        foot_status.held_col = foot_status.last_col;
+    }
+
+    // How many feet aren't pinned on a hold, and so can take the steps of the next row.
+    pub fn free_feet(&self) -> usize {
+        self.feet_status.iter().filter(|fs| !fs.holding()).count()
+    }
+
+    // Keeps feet off col until release() is called.
+    pub fn block(&mut self, col: i8) {
+        self.blocked.push(col);
+    }
+
+    pub fn release(&mut self, col: i8) {
+        for foot_status in &mut self.feet_status {
+            if foot_status.held_col == Some(col) {
+                foot_status.held_col = None;
+            }
+        }
+        self.blocked.retain(|c| *c != col);
+    }
+
+    // Returns the panel a mine in input_col stays on because a foot last stepped on input_col
+    // there (footswitch and jack mines), unless it is in taken.
+    pub fn foot_mine_col(&self, input_col: i8, taken: &[i8]) -> Option<i8> {
+        self.feet_status
+            .iter()
+            .filter(|fs| fs.last_input_col == Some(input_col))
+            .filter_map(|fs| fs.last_col)
+            .find(|lc| self.is_free_col(*lc, taken))
+    }
+
+    // Returns the panels other mines can go on, those neither foot is on and not in taken, in
+    // random order. Where the feet step next isn't known yet, so the mine goes on the first one
+    // and sm::move_mines() moves it along if the next step lands on it.
+    pub fn mine_cols(&mut self, taken: &[i8]) -> Vec<i8> {
+        let mut cols: Vec<i8> = (0..self.style.num_cols())
+            .filter(|c| self.is_free_col(*c, taken) && !self.is_under_foot(*c))
+            .collect();
+        cols.shuffle(&mut self.rand);
+        cols
+    }
+
+    // Chooses a panel next to foot for it to bracket, avoiding the columns in taken.
+    pub fn bracket_col(&mut self, foot: Foot, taken: &[i8]) -> Option<i8> {
+        let foot_col = self.feet_status[foot as usize].last_col?;
+        self.choose_free_col(taken, |g, c| {
+            !g.is_under_foot(c) && g.style.can_bracket(foot_col, c)
+        })
+    }
+
+    // Chooses a panel for a hand to press, avoiding the columns in taken.
+    pub fn hand_col(&mut self, taken: &[i8]) -> Option<i8> {
+        self.choose_free_col(taken, |g, c| !g.is_under_foot(c))
+    }
+
+    fn is_under_foot(&self, col: i8) -> bool {
+        self.feet_status
+            .iter()
+            .any(|fs| fs.last_col.is_some_and(|lc| self.shares_panel(lc, col)))
+    }
+
+    fn is_free_col(&self, col: i8, taken: &[i8]) -> bool {
+        !taken.iter().any(|t| self.shares_panel(*t, col)) && !self.is_held(col)
+    }
+
+    fn choose_free_col(&mut self, taken: &[i8], filter: impl Fn(&Self, i8) -> bool) -> Option<i8> {
+        let cols: Vec<i8> = (0..self.style.num_cols())
+            .filter(|c| self.is_free_col(*c, taken) && filter(self, *c))
+            .collect();
+        cols.choose(&mut self.rand).copied()
+    }
+
     fn next_zone(&mut self) -> Zone {
         Self::rand_zone(
             self.style,
@@ -327,6 +651,8 @@ fn test_rand_zone() {
 
 impl Generator {
     const EPSILON: f32 = 0.00001;
+    // log probability lost for every rule relaxed, so that beam search avoids relaxing
+    const RELAXATION_PENALTY: f32 = 20.0;
 
     fn next_foot_status(&self) -> &FootStatus {
         &self.feet_status[self.next_foot as usize]
@@ -382,124 +708,177 @@ impl Generator {
         Some(l.angle(r, self.prev_angle))
     }
 
+    fn time_since_next_foot_stepped(&self) -> Option<f32> {
+        Some(self.time? - self.next_foot_status().last_time?)
+    }
+
+    fn shares_panel(&self, col1: i8, col2: i8) -> bool {
+        let sm_cols2 = self.style.sm_cols_for_col(col2);
+        self.style
+            .sm_cols_for_col(col1)
+            .iter()
+            .any(|sc1| sm_cols2.contains(sc1))
+    }
+
+    // Whether col is held down by a foot, hand or bracket.
+    fn is_held(&self, col: i8) -> bool {
+        self.feet_status.iter().any(|fs| {
+            fs.held_col
+                .is_some_and(|held_col| self.shares_panel(held_col, col))
+        }) || self.blocked.iter().any(|b| self.shares_panel(*b, col))
+    }
+
     fn is_valid_col(&self, col: i8) -> bool {
-        if self.params.disallow_footswitch {
-            if let Some(last_col) = self.prev_foot_status().last_col {
-                let sm_cols1 = self.style.sm_cols_for_col(last_col);
-                let sm_cols2 = self.style.sm_cols_for_col(col);
-                for sc1 in &sm_cols1 {
-                    for sc2 in &sm_cols2 {
-                        if sc1 == sc2 {
-                            return false;
-                        }
-                    }
-                }
-            }
+        self.is_valid_col_with_relaxed(col, 0)
+    }
+
+    // Like is_valid_col(), but ignoring the first relaxed rules of Rule::ALL.
+    fn is_valid_col_with_relaxed(&self, col: i8, relaxed: usize) -> bool {
+        if self.is_held(col) {
+            return false;
         }
-        if let Some(mr) = self.params.max_repeated {
-            if self.next_foot_status().last_col == Some(col)
-                && self.next_foot_status().repeated >= mr
-            {
+        if self.params.disallow_footswitch
+            && Rule::DisallowFootswitch.is_enforced(relaxed)
+            && let Some(last_col) = self.prev_foot_status().last_col
+            && self.shares_panel(last_col, col)
+        {
+            return false;
+        }
+        if let Some(mr) = self.params.max_repeated
+            && Rule::MaxRepeated.is_enforced(relaxed)
+            && self.next_foot_status().last_col == Some(col)
+            && self.next_foot_status().repeated >= mr
+        {
+            return false;
+        }
+        if let Some(md) = self.params.max_dist_between_feet
+            && Rule::MaxDistBetweenFeet.is_enforced(relaxed)
+            && let Some(prev_col) = self.prev_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let cur_coord = self.style.coord(col);
+            if prev_coord.dist(cur_coord) > md + Self::EPSILON {
                 return false;
             }
         }
-        if let Some(md) = self.params.max_dist_between_feet {
-            if let Some(prev_col) = self.prev_foot_status().last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let cur_coord = self.style.coord(col);
-                if prev_coord.dist(cur_coord) > md + Self::EPSILON {
-                    return false;
-                }
+        if let Some(md) = self.params.max_dist_between_feet_if_crossover
+            && Rule::MaxDistBetweenFeetIfCrossover.is_enforced(relaxed)
+            && let Some(prev_col) = self.prev_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let cur_coord = self.style.coord(col);
+            if prev_coord.dist(cur_coord) > md + Self::EPSILON && self.test_crossover(col) {
+                return false;
             }
         }
-        if let Some(md) = self.params.max_dist_between_feet_if_crossover {
-            if let Some(prev_col) = self.prev_foot_status().last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let cur_coord = self.style.coord(col);
-                if prev_coord.dist(cur_coord) > md + Self::EPSILON && self.test_crossover(col) {
-                    return false;
-                }
+        if let Some(md) = self.params.max_dist_between_steps
+            && Rule::MaxDistBetweenSteps.is_enforced(relaxed)
+            && let Some(prev_col) = self.next_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let cur_coord = self.style.coord(col);
+            if prev_coord.dist(cur_coord) > md + Self::EPSILON {
+                return false;
             }
         }
-        if let Some(md) = self.params.max_dist_between_steps {
-            if let Some(prev_col) = self.next_foot_status().last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let cur_coord = self.style.coord(col);
-                if prev_coord.dist(cur_coord) > md + Self::EPSILON {
-                    return false;
-                }
+        if let Some(md) = self.params.max_horizontal_dist_between_steps
+            && Rule::MaxHorizontalDistBetweenSteps.is_enforced(relaxed)
+            && let Some(prev_col) = self.next_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let cur_coord = self.style.coord(col);
+            if (prev_coord.0 - cur_coord.0).abs() > md + Self::EPSILON {
+                return false;
             }
         }
-        if let Some(md) = self.params.max_horizontal_dist_between_steps {
-            if let Some(prev_col) = self.next_foot_status().last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let cur_coord = self.style.coord(col);
-                if (prev_coord.0 - cur_coord.0).abs() > md + Self::EPSILON {
-                    return false;
-                }
+        if let Some(md) = self.params.max_horizontal_dist_between_steps_if_crossover
+            && Rule::MaxHorizontalDistBetweenStepsIfCrossover.is_enforced(relaxed)
+            && let Some(prev_col) = self.next_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let cur_coord = self.style.coord(col);
+            if (prev_coord.0 - cur_coord.0).abs() > md + Self::EPSILON && self.test_crossover(col) {
+                return false;
             }
         }
-        if let Some(md) = self.params.max_horizontal_dist_between_steps_if_crossover {
-            if let Some(prev_col) = self.next_foot_status().last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let cur_coord = self.style.coord(col);
-                if (prev_coord.0 - cur_coord.0).abs() > md + Self::EPSILON
-                    && self.test_crossover(col)
-                {
-                    return false;
-                }
+        if let Some(md) = self.params.max_vertical_dist_between_steps
+            && Rule::MaxVerticalDistBetweenSteps.is_enforced(relaxed)
+            && let Some(prev_col) = self.next_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let cur_coord = self.style.coord(col);
+            if (prev_coord.1 - cur_coord.1).abs() > md + Self::EPSILON {
+                return false;
             }
         }
-        if let Some(md) = self.params.max_vertical_dist_between_steps {
-            if let Some(prev_col) = self.next_foot_status().last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let cur_coord = self.style.coord(col);
-                if (prev_coord.1 - cur_coord.1).abs() > md + Self::EPSILON {
-                    return false;
-                }
+        if let Some((dist, dist_per_second)) = self.params.timed_max_dist_between_steps
+            && Rule::TimedMaxDistBetweenSteps.is_enforced(relaxed)
+            && let Some(elapsed) = self.time_since_next_foot_stepped()
+            && let Some(prev_col) = self.next_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let cur_coord = self.style.coord(col);
+            if prev_coord.dist(cur_coord) > dist + dist_per_second * elapsed + Self::EPSILON {
+                return false;
             }
         }
-        if let Some(md) = self.params.max_horizontal_dist_between_4_steps_both_feet {
-            if let Some(prev_col) = self.prev_foot_status().last_last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let cur_coord = self.style.coord(col);
-                if (prev_coord.0 - cur_coord.0).abs() > md + Self::EPSILON {
-                    return false;
-                }
+        if let Some((dist, dist_per_second)) = self.params.timed_max_horizontal_dist_between_steps
+            && Rule::TimedMaxHorizontalDistBetweenSteps.is_enforced(relaxed)
+            && let Some(elapsed) = self.time_since_next_foot_stepped()
+            && let Some(prev_col) = self.next_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let cur_coord = self.style.coord(col);
+            if (prev_coord.0 - cur_coord.0).abs() > dist + dist_per_second * elapsed + Self::EPSILON
+            {
+                return false;
             }
         }
-        if let Some(dist) = self.params.max_horizontal_dist_between_3_steps_same_foot {
-            if let Some(prev_col) = self.next_foot_status().last_last_col {
-                let cur_coord = self.style.coord(col);
-                let prev_coord = self.style.coord(prev_col);
-                let over_dist = (prev_coord.0 - cur_coord.0).abs() - dist;
-                if over_dist > 0.0 {
-                    return false;
-                }
+        if let Some(md) = self.params.max_horizontal_dist_between_4_steps_both_feet
+            && Rule::MaxHorizontalDistBetween4StepsBothFeet.is_enforced(relaxed)
+            && let Some(prev_col) = self.prev_foot_status().last_last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let cur_coord = self.style.coord(col);
+            if (prev_coord.0 - cur_coord.0).abs() > md + Self::EPSILON {
+                return false;
             }
         }
-        if let Some(ma) = self.params.max_angle {
-            if let Some(a) = self.test_angle(col) {
-                if a.abs() > ma + Self::EPSILON {
-                    return false;
-                }
+        if let Some(dist) = self.params.max_horizontal_dist_between_3_steps_same_foot
+            && Rule::MaxHorizontalDistBetween3StepsSameFoot.is_enforced(relaxed)
+            && let Some(prev_col) = self.next_foot_status().last_last_col
+        {
+            let cur_coord = self.style.coord(col);
+            let prev_coord = self.style.coord(prev_col);
+            let over_dist = (prev_coord.0 - cur_coord.0).abs() - dist;
+            if over_dist > 0.0 {
+                return false;
             }
         }
-        if let Some(mt) = self.params.max_turn {
-            if let Some(a) = self.test_angle(col) {
-                if (a - self.prev_angle).abs() > mt + Self::EPSILON {
-                    return false;
-                }
-            }
+        if let Some(ma) = self.params.max_angle
+            && Rule::MaxAngle.is_enforced(relaxed)
+            && let Some(a) = self.test_angle(col)
+            && a.abs() > ma + Self::EPSILON
+        {
+            return false;
         }
-        if let Some(ma) = self.params.max_bar_angle {
-            if let Some(a) = self.test_bar_angle(col) {
-                if -a > ma + Self::EPSILON {
-                    return false;
-                }
-            }
+        if let Some(mt) = self.params.max_turn
+            && Rule::MaxTurn.is_enforced(relaxed)
+            && let Some(a) = self.test_angle(col)
+            && (a - self.prev_angle).abs() > mt + Self::EPSILON
+        {
+            return false;
+        }
+        if let Some(ma) = self.params.max_bar_angle
+            && Rule::MaxBarAngle.is_enforced(relaxed)
+            && let Some(a) = self.test_bar_angle(col)
+            && -a > ma + Self::EPSILON
+        {
+            return false;
         }
-        if self.params.disallow_foot_opposite_side {
+        if self.params.disallow_foot_opposite_side
+            && Rule::DisallowFootOppositeSide.is_enforced(relaxed)
+        {
             let coord = self.style.coord(col);
             match self.next_foot {
                 Foot::Left => {
@@ -525,102 +904,102 @@ impl Generator {
     fn prob_with_input_col(&self, col: i8, input_col: i8) -> f32 {
         let mut prob = 1.0;
         let cur_coord = self.style.coord(col);
-        if let Some((repeated, decay)) = self.params.repeated_decay {
-            if self.next_foot_status().last_col == Some(col) {
-                let over_repeated = self.next_foot_status().repeated - repeated;
-                if over_repeated > 0 {
-                    prob *= decay;
-                }
-            }
-        }
-        if let Some(decay) = self.params.other_foot_repeat_decay {
-            if self.prev_foot_status().repeated > 1 && self.next_foot_status().last_col == Some(col)
-            {
+        if let Some((repeated, decay)) = self.params.repeated_decay
+            && self.next_foot_status().last_col == Some(col)
+        {
+            let over_repeated = self.next_foot_status().repeated - repeated;
+            if over_repeated > 0 {
                 prob *= decay;
             }
         }
-        if let Some((dist, decay)) = self.params.dist_between_feet_decay {
-            if let Some(prev_col) = self.prev_foot_status().last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let over_dist = prev_coord.dist(cur_coord) - dist;
-                if over_dist > 0.0 {
-                    prob *= decay.powf(over_dist);
-                }
-            }
+        if let Some(decay) = self.params.other_foot_repeat_decay
+            && self.prev_foot_status().repeated > 1
+            && self.next_foot_status().last_col == Some(col)
+        {
+            prob *= decay;
         }
-        if let Some((dist, decay)) = self.params.dist_between_steps_decay {
-            if let Some(prev_col) = self.next_foot_status().last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let over_dist = prev_coord.dist(cur_coord) - dist;
-                if over_dist > 0.0 {
-                    prob *= decay.powf(over_dist);
-                }
+        if let Some((dist, decay)) = self.params.dist_between_feet_decay
+            && let Some(prev_col) = self.prev_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let over_dist = prev_coord.dist(cur_coord) - dist;
+            if over_dist > 0.0 {
+                prob *= decay.powf(over_dist);
             }
         }
-        if let Some((dist, decay)) = self.params.horizontal_dist_between_steps_decay {
-            if let Some(prev_col) = self.next_foot_status().last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let over_dist = (prev_coord.0 - cur_coord.0).abs() - dist;
-                if over_dist > 0.0 {
-                    prob *= decay.powf(over_dist);
-                }
+        if let Some((dist, decay)) = self.params.dist_between_steps_decay
+            && let Some(prev_col) = self.next_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let over_dist = prev_coord.dist(cur_coord) - dist;
+            if over_dist > 0.0 {
+                prob *= decay.powf(over_dist);
             }
         }
-        if let Some((dist, decay)) = self.params.vertical_dist_between_steps_decay {
-            if let Some(prev_col) = self.next_foot_status().last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let over_dist = (prev_coord.1 - cur_coord.1).abs() - dist;
-                if over_dist > 0.0 {
-                    prob *= decay.powf(over_dist);
-                }
+        if let Some((dist, decay)) = self.params.horizontal_dist_between_steps_decay
+            && let Some(prev_col) = self.next_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let over_dist = (prev_coord.0 - cur_coord.0).abs() - dist;
+            if over_dist > 0.0 {
+                prob *= decay.powf(over_dist);
             }
         }
-        if let Some((dist, decay)) = self.params.horizontal_dist_between_3_steps_same_foot_decay {
-            if let Some(prev_col) = self.next_foot_status().last_last_col {
-                let prev_coord = self.style.coord(prev_col);
-                let cur_coord = self.style.coord(col);
-                let over_dist = (prev_coord.0 - cur_coord.0).abs() - dist;
-                if over_dist > 0.0 {
-                    prob *= decay.powf(over_dist);
-                }
+        if let Some((dist, decay)) = self.params.vertical_dist_between_steps_decay
+            && let Some(prev_col) = self.next_foot_status().last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let over_dist = (prev_coord.1 - cur_coord.1).abs() - dist;
+            if over_dist > 0.0 {
+                prob *= decay.powf(over_dist);
             }
         }
-        if let Some((angle, decay)) = self.params.angle_decay {
-            if let Some(a) = self.test_angle(col) {
-                let over_angle = a.abs() - angle;
-                if over_angle > 0.0 {
-                    prob *= decay.powf(over_angle);
-                }
+        if let Some((dist, decay)) = self.params.horizontal_dist_between_3_steps_same_foot_decay
+            && let Some(prev_col) = self.next_foot_status().last_last_col
+        {
+            let prev_coord = self.style.coord(prev_col);
+            let cur_coord = self.style.coord(col);
+            let over_dist = (prev_coord.0 - cur_coord.0).abs() - dist;
+            if over_dist > 0.0 {
+                prob *= decay.powf(over_dist);
             }
         }
-        if let Some((turn, decay)) = self.params.turn_decay {
-            if let Some(a) = self.test_angle(col) {
-                let over_angle = (a - self.prev_angle).abs() - turn;
-                if over_angle > 0.0 {
-                    prob *= decay.powf(over_angle);
-                }
+        if let Some((angle, decay)) = self.params.angle_decay
+            && let Some(a) = self.test_angle(col)
+        {
+            let over_angle = a.abs() - angle;
+            if over_angle > 0.0 {
+                prob *= decay.powf(over_angle);
             }
         }
-        if let Some(cm) = self.params.crossover_multiplier {
-            if self.test_crossover(col) {
-                prob *= cm;
+        if let Some((turn, decay)) = self.params.turn_decay
+            && let Some(a) = self.test_angle(col)
+        {
+            let over_angle = (a - self.prev_angle).abs() - turn;
+            if over_angle > 0.0 {
+                prob *= decay.powf(over_angle);
             }
         }
-        if let Some((angle, decay)) = self.params.bar_angle_decay {
-            if let Some(a) = self.test_bar_angle(col) {
-                let over_angle = -a - angle;
-                if over_angle > 0.0 {
-                    prob *= decay.powf(over_angle);
-                }
+        if let Some(cm) = self.params.crossover_multiplier
+            && self.test_crossover(col)
+        {
+            prob *= cm;
+        }
+        if let Some((angle, decay)) = self.params.bar_angle_decay
+            && let Some(a) = self.test_bar_angle(col)
+        {
+            let over_angle = -a - angle;
+            if over_angle > 0.0 {
+                prob *= decay.powf(over_angle);
             }
         }
         // if input column is same as previous input column, penalize if same column as before
-        if let Some(different_decay) = self.params.preserve_input_repetitions {
-            if let Some(last_input_col) = self.next_foot_status().last_input_col {
-                if input_col != last_input_col && Some(col) == self.next_foot_status().last_col {
-                    prob *= different_decay;
-                }
-            }
+        if let Some(different_decay) = self.params.preserve_input_repetitions
+            && let Some(last_input_col) = self.next_foot_status().last_input_col
+            && input_col != last_input_col
+            && Some(col) == self.next_foot_status().last_col
+        {
+            prob *= different_decay;
         }
         if let Some((dist, decay)) = self.params.doubles_movement {
             let zone_x = self.zone.current_x()
@@ -645,19 +1024,77 @@ impl Generator {
 #[test]
 fn test_choose_from_probs_with_prob() {
     assert_eq!(
-        Generator::choose_from_probs_with_prob(vec![(5, 0.1)], 0.05),
-        5
+        Generator::choose_from_probs_with_prob(&[(5, 0.1)], 0.05),
+        (5, 0.1)
     );
     assert_eq!(
-        Generator::choose_from_probs_with_prob(vec![(5, 0.1), (6, 0.1)], 0.05),
-        5
+        Generator::choose_from_probs_with_prob(&[(5, 0.1), (6, 0.1)], 0.05),
+        (5, 0.1)
     );
     assert_eq!(
-        Generator::choose_from_probs_with_prob(vec![(5, 0.1), (6, 0.1)], 0.15),
-        6
+        Generator::choose_from_probs_with_prob(&[(5, 0.1), (6, 0.2)], 0.15),
+        (6, 0.2)
     );
 }
 
+#[test]
+fn try_generate() {
+    use approx::assert_relative_eq;
+    let params = GeneratorParameters {
+        disallow_footswitch: true,
+        max_dist_between_feet: Some(1.0),
+        ..GeneratorParameters::default()
+    };
+    let mut g = Generator::new(Style::ItgSingles, params);
+    g.next_foot = Foot::Left;
+    g.step(0);
+    g.step(3);
+    assert!(g.try_generate_with_input_col(-1, false).is_some());
+    assert_eq!(g.take_relaxations(), vec![Rule::MaxDistBetweenFeet]);
+    assert!(g.take_relaxations().is_empty());
+
+    // the only column close enough is a repeat that can't happen, which relaxes too
+    let mut g = Generator::new(
+        Style::ItgSingles,
+        GeneratorParameters {
+            max_dist_between_steps: Some(0.5),
+            repeated_decay: Some((0, 0.0)),
+            ..GeneratorParameters::default()
+        },
+    );
+    g.next_foot = Foot::Left;
+    g.step(0);
+    g.step(3);
+    assert_eq!(g.valid_cols(), vec![0]);
+    assert!(
+        g.try_generate_with_input_col(-1, false)
+            .is_some_and(|c| c != 0)
+    );
+    assert_eq!(g.take_relaxations(), vec![Rule::MaxDistBetweenSteps]);
+    for (i, rule) in Rule::ALL.iter().enumerate() {
+        assert_eq!(*rule as usize, i);
+    }
+
+    // nothing is free while both feet hold and a hand blocks the rest
+    let mut g = Generator::new(Style::ItgSingles, GeneratorParameters::default());
+    g.next_foot = Foot::Left;
+    g.step(0);
+    g.hold_last_step();
+    g.step(3);
+    g.hold_last_step();
+    g.block(1);
+    g.block(2);
+    assert_eq!(g.try_generate_with_input_col(-1, false), None);
+    assert_eq!(g.log_prob(), 0.0);
+
+    let mut g = Generator::new(Style::ItgSingles, GeneratorParameters::default());
+    g.next_foot = Foot::Left;
+    g.step(0);
+    g.step(3);
+    assert!(g.try_generate_with_input_col(-1, false).is_some());
+    assert_relative_eq!(g.log_prob(), 0.25_f32.ln());
+}
+
 #[test]
 fn sanity() {
     let mut g = Generator::new(Style::ItgSingles, GeneratorParameters::default());
@@ -696,8 +1133,10 @@ fn state() {
 
 #[test]
 fn preserve_input_repetitions() {
-    let mut params = GeneratorParameters::default();
-    params.preserve_input_repetitions = Some(1.0);
+    let params = GeneratorParameters {
+        preserve_input_repetitions: Some(1.0),
+        ..GeneratorParameters::default()
+    };
     let mut g = Generator::new(Style::HorizonDoubles, params);
 
     let f = g.next_foot;
@@ -759,6 +1198,64 @@ fn preserve_input_repetitions_jump() {
     assert_eq!(g.prev_foot_status().last_input_col, Some(6));
 }
 
+#[test]
+fn holds() {
+    let mut g = Generator::new(Style::ItgSingles, GeneratorParameters::default());
+    g.next_foot = Foot::Left;
+    g.step(0);
+    g.hold_last_step();
+    assert!(g.prev_foot_status().holding());
+    assert_eq!(g.valid_cols(), vec![1, 2, 3]);
+    for _ in 0..10 {
+        assert_ne!(g.generate(), 0);
+        assert_eq!(g.feet_status[Foot::Left as usize].last_col, Some(0));
+    }
+    g.release(0);
+    assert!(!g.feet_status[Foot::Left as usize].holding());
+    assert_eq!(g.next_foot, Foot::Left);
+    assert_eq!(g.valid_cols(), vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn mines() {
+    let mut g = Generator::new(Style::ItgSingles, GeneratorParameters::default());
+    g.next_foot = Foot::Left;
+    g.step_with_input_col(0, 0);
+    g.step_with_input_col(3, 1);
+    assert_eq!(g.foot_mine_col(0, &[]), Some(0));
+    assert_eq!(g.foot_mine_col(1, &[]), Some(3));
+    assert_eq!(g.foot_mine_col(2, &[]), None);
+    assert_eq!(g.foot_mine_col(1, &[3, 1]), None);
+    for _ in 0..10 {
+        let mut cols = g.mine_cols(&[]);
+        cols.sort();
+        assert_eq!(cols, vec![1, 2]);
+    }
+    assert_eq!(g.mine_cols(&[3, 1]), vec![2]);
+    assert!(g.mine_cols(&[1, 2]).is_empty());
+}
+
+#[test]
+fn hands_and_brackets() {
+    let mut g = Generator::new(Style::ItgSingles, GeneratorParameters::default());
+    g.next_foot = Foot::Left;
+    g.step(0);
+    g.step(3);
+    for _ in 0..10 {
+        let c = g.hand_col(&[]).unwrap();
+        assert!(c == 1 || c == 2);
+    }
+    assert_eq!(g.hand_col(&[1, 2]), None);
+    assert_eq!(g.bracket_col(Foot::Left, &[1]), Some(2));
+    assert_eq!(g.bracket_col(Foot::Right, &[2]), Some(1));
+
+    g.block(1);
+    assert_eq!(g.hand_col(&[]), Some(2));
+    assert_eq!(g.valid_cols(), vec![0, 2, 3]);
+    g.release(1);
+    assert_eq!(g.valid_cols(), vec![0, 1, 2, 3]);
+}
+
 #[test]
 fn valid_steps() {
     use std::f32::consts::PI;
@@ -781,8 +1278,10 @@ fn valid_steps() {
     }
     // no footswitches
     {
-        let mut params = GeneratorParameters::default();
-        params.disallow_footswitch = true;
+        let params = GeneratorParameters {
+            disallow_footswitch: true,
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgSingles, params);
         g.next_foot = Foot::Left;
         g.step(0);
@@ -791,8 +1290,10 @@ fn valid_steps() {
     }
     // no footswitches pump brackets
     {
-        let mut params = GeneratorParameters::default();
-        params.disallow_footswitch = true;
+        let params = GeneratorParameters {
+            disallow_footswitch: true,
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::PumpDoublesBrackets, params);
         g.next_foot = Foot::Left;
         g.step(3);
@@ -801,8 +1302,10 @@ fn valid_steps() {
     }
     // max repeated
     {
-        let mut params = GeneratorParameters::default();
-        params.max_repeated = Some(2);
+        let params = GeneratorParameters {
+            max_repeated: Some(2),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgSingles, params);
         g.next_foot = Foot::Left;
         g.step(0);
@@ -817,8 +1320,10 @@ fn valid_steps() {
     }
     // max dist two feet
     {
-        let mut params = GeneratorParameters::default();
-        params.max_dist_between_feet = Some(2.0);
+        let params = GeneratorParameters {
+            max_dist_between_feet: Some(2.0),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgDoubles, params);
         g.next_foot = Foot::Right;
         g.step(3);
@@ -829,8 +1334,10 @@ fn valid_steps() {
     }
     // max dist two feet if crossover
     {
-        let mut params = GeneratorParameters::default();
-        params.max_dist_between_feet_if_crossover = Some(1.9);
+        let params = GeneratorParameters {
+            max_dist_between_feet_if_crossover: Some(1.9),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgDoubles, params);
         g.next_foot = Foot::Right;
         g.step(4);
@@ -841,8 +1348,10 @@ fn valid_steps() {
     }
     // max dist steps
     {
-        let mut params = GeneratorParameters::default();
-        params.max_dist_between_steps = Some(2.0);
+        let params = GeneratorParameters {
+            max_dist_between_steps: Some(2.0),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgDoubles, params);
         g.next_foot = Foot::Left;
         g.step(0);
@@ -853,8 +1362,10 @@ fn valid_steps() {
     }
     // max horizontal dist between steps
     {
-        let mut params = GeneratorParameters::default();
-        params.max_horizontal_dist_between_steps = Some(1.0);
+        let params = GeneratorParameters {
+            max_horizontal_dist_between_steps: Some(1.0),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonSingles, params);
         g.next_foot = Foot::Left;
         g.step(1);
@@ -863,8 +1374,10 @@ fn valid_steps() {
     }
     // max horizontal dist between steps if crossover
     {
-        let mut params = GeneratorParameters::default();
-        params.max_horizontal_dist_between_steps_if_crossover = Some(1.5);
+        let params = GeneratorParameters {
+            max_horizontal_dist_between_steps_if_crossover: Some(1.5),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonSingles, params);
         g.next_foot = Foot::Left;
         g.step(1);
@@ -875,18 +1388,57 @@ fn valid_steps() {
     }
     // max vertical dist steps
     {
-        let mut params = GeneratorParameters::default();
-        params.max_vertical_dist_between_steps = Some(1.0);
+        let params = GeneratorParameters {
+            max_vertical_dist_between_steps: Some(1.0),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonSingles, params);
         g.next_foot = Foot::Left;
         g.step(3);
         g.step(7);
         assert_eq!(g.valid_cols(), vec![0, 1, 3, 4, 7, 8]);
     }
+    // timed max dist steps
+    {
+        let params = GeneratorParameters {
+            timed_max_dist_between_steps: Some((1.0, 4.0)),
+            ..GeneratorParameters::default()
+        };
+        let mut g = Generator::new(Style::ItgDoubles, params);
+        g.next_foot = Foot::Left;
+        g.set_time(0.0);
+        g.step(3);
+        g.step(4);
+        g.set_time(0.125);
+        assert_eq!(g.valid_cols(), vec![1, 2, 3, 4]);
+        g.set_time(0.25);
+        assert_eq!(g.valid_cols(), vec![0, 1, 2, 3, 4]);
+        g.set_time(0.5);
+        assert_eq!(g.valid_cols(), vec![0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+    // timed max horizontal dist steps
+    {
+        let params = GeneratorParameters {
+            timed_max_horizontal_dist_between_steps: Some((0.0, 4.0)),
+            ..GeneratorParameters::default()
+        };
+        let mut g = Generator::new(Style::ItgDoubles, params);
+        g.next_foot = Foot::Left;
+        g.step(3);
+        g.step(4);
+        assert_eq!(g.valid_cols(), vec![0, 1, 2, 3, 4, 5, 6, 7]);
+        g.set_time(0.0);
+        g.step(3);
+        g.step(4);
+        g.set_time(0.25);
+        assert_eq!(g.valid_cols(), vec![1, 2, 3, 4]);
+    }
     // max horizontal dist both foot 4 steps
     {
-        let mut params = GeneratorParameters::default();
-        params.max_horizontal_dist_between_4_steps_both_feet = Some(2.5);
+        let params = GeneratorParameters {
+            max_horizontal_dist_between_4_steps_both_feet: Some(2.5),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgDoubles, params);
         g.next_foot = Foot::Left;
         g.step(0);
@@ -899,8 +1451,10 @@ fn valid_steps() {
     }
     // max angle
     {
-        let mut params = GeneratorParameters::default();
-        params.max_angle = Some(PI * 3.0 / 4.0);
+        let params = GeneratorParameters {
+            max_angle: Some(PI * 3.0 / 4.0),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonSingles, params);
         g.next_foot = Foot::Left;
         g.step(1);
@@ -913,8 +1467,10 @@ fn valid_steps() {
     }
     // max turn
     {
-        let mut params = GeneratorParameters::default();
-        params.max_turn = Some(PI / 2.0);
+        let params = GeneratorParameters {
+            max_turn: Some(PI / 2.0),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonSingles, params);
         g.next_foot = Foot::Left;
         g.step(3);
@@ -927,8 +1483,10 @@ fn valid_steps() {
     }
     // max bar angle
     {
-        let mut params = GeneratorParameters::default();
-        params.max_bar_angle = Some(0.3);
+        let params = GeneratorParameters {
+            max_bar_angle: Some(0.3),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgDoubles, params);
         g.next_foot = Foot::Right;
         g.step(4);
@@ -937,8 +1495,10 @@ fn valid_steps() {
     }
     // foot other side
     {
-        let mut params = GeneratorParameters::default();
-        params.disallow_foot_opposite_side = true;
+        let params = GeneratorParameters {
+            disallow_foot_opposite_side: true,
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgDoubles, params);
         g.next_foot = Foot::Left;
         assert_eq!(g.valid_cols(), vec![0, 1, 2, 3, 4, 5, 6]);
@@ -947,8 +1507,10 @@ fn valid_steps() {
     }
     // horizontal dist between 3 foot steps
     {
-        let mut params = GeneratorParameters::default();
-        params.max_horizontal_dist_between_3_steps_same_foot = Some(1.0);
+        let params = GeneratorParameters {
+            max_horizontal_dist_between_3_steps_same_foot: Some(1.0),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonSingles, params);
         g.next_foot = Foot::Left;
         g.step(2);
@@ -972,8 +1534,10 @@ fn steps_prob() {
     use std::f32::consts::PI;
     // repeated decay
     {
-        let mut params = GeneratorParameters::default();
-        params.repeated_decay = Some((2, 0.5));
+        let params = GeneratorParameters {
+            repeated_decay: Some((2, 0.5)),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgSingles, params);
         g.next_foot = Foot::Left;
         g.step(0);
@@ -994,8 +1558,10 @@ fn steps_prob() {
     }
     // other foot repeated decay
     {
-        let mut params = GeneratorParameters::default();
-        params.other_foot_repeat_decay = Some(0.5);
+        let params = GeneratorParameters {
+            other_foot_repeat_decay: Some(0.5),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgSingles, params);
         g.next_foot = Foot::Left;
         g.step(0);
@@ -1031,8 +1597,10 @@ fn steps_prob() {
     }
     // dist between feet decay
     {
-        let mut params = GeneratorParameters::default();
-        params.dist_between_feet_decay = Some((1.0, 0.5));
+        let params = GeneratorParameters {
+            dist_between_feet_decay: Some((1.0, 0.5)),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgDoubles, params);
         g.next_foot = Foot::Right;
         g.step(4);
@@ -1044,8 +1612,10 @@ fn steps_prob() {
     }
     // dist between steps decay
     {
-        let mut params = GeneratorParameters::default();
-        params.dist_between_steps_decay = Some((1.0, 0.5));
+        let params = GeneratorParameters {
+            dist_between_steps_decay: Some((1.0, 0.5)),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgDoubles, params);
         g.next_foot = Foot::Left;
         g.step(3);
@@ -1057,8 +1627,10 @@ fn steps_prob() {
     }
     // horizontal dist between steps decay
     {
-        let mut params = GeneratorParameters::default();
-        params.horizontal_dist_between_steps_decay = Some((1.0, 0.5));
+        let params = GeneratorParameters {
+            horizontal_dist_between_steps_decay: Some((1.0, 0.5)),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonSingles, params);
         g.next_foot = Foot::Left;
         g.step(1);
@@ -1075,8 +1647,10 @@ fn steps_prob() {
     }
     // vertical dist between steps decay
     {
-        let mut params = GeneratorParameters::default();
-        params.vertical_dist_between_steps_decay = Some((1.0, 0.5));
+        let params = GeneratorParameters {
+            vertical_dist_between_steps_decay: Some((1.0, 0.5)),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonSingles, params);
         g.next_foot = Foot::Left;
         g.step(3);
@@ -1093,8 +1667,10 @@ fn steps_prob() {
     }
     // horizontal dist same foot 3 steps
     {
-        let mut params = GeneratorParameters::default();
-        params.horizontal_dist_between_3_steps_same_foot_decay = Some((1.0, 0.5));
+        let params = GeneratorParameters {
+            horizontal_dist_between_3_steps_same_foot_decay: Some((1.0, 0.5)),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonSingles, params);
         g.next_foot = Foot::Left;
         g.step(2);
@@ -1145,16 +1721,20 @@ fn steps_prob() {
     // angle decay
     {
         {
-            let mut params = GeneratorParameters::default();
-            params.angle_decay = Some((PI / 2.0, 0.5));
+            let params = GeneratorParameters {
+                angle_decay: Some((PI / 2.0, 0.5)),
+                ..GeneratorParameters::default()
+            };
             let mut g = Generator::new(Style::HorizonSingles, params);
             g.next_foot = Foot::Left;
             g.step(1);
             g.step(1);
         }
         {
-            let mut params = GeneratorParameters::default();
-            params.angle_decay = Some((PI / 2.0, 0.5));
+            let params = GeneratorParameters {
+                angle_decay: Some((PI / 2.0, 0.5)),
+                ..GeneratorParameters::default()
+            };
             let mut g = Generator::new(Style::HorizonSingles, params);
             g.next_foot = Foot::Right;
             g.step(7);
@@ -1170,8 +1750,10 @@ fn steps_prob() {
     // turn decay
     {
         {
-            let mut params = GeneratorParameters::default();
-            params.turn_decay = Some((PI / 2.0, 0.5));
+            let params = GeneratorParameters {
+                turn_decay: Some((PI / 2.0, 0.5)),
+                ..GeneratorParameters::default()
+            };
             let mut g = Generator::new(Style::HorizonSingles, params);
             g.next_foot = Foot::Left;
             g.step(1);
@@ -1184,8 +1766,10 @@ fn steps_prob() {
             assert_relative_eq!(g.prob(7), 0.5_f32.powf(PI / 2.0));
         }
         {
-            let mut params = GeneratorParameters::default();
-            params.turn_decay = Some((PI / 2.0, 0.5));
+            let params = GeneratorParameters {
+                turn_decay: Some((PI / 2.0, 0.5)),
+                ..GeneratorParameters::default()
+            };
             let mut g = Generator::new(Style::HorizonSingles, params);
             g.next_foot = Foot::Right;
             g.step(7);
@@ -1200,8 +1784,10 @@ fn steps_prob() {
     }
     // crossover multiplier
     {
-        let mut params = GeneratorParameters::default();
-        params.crossover_multiplier = Some(2.0);
+        let params = GeneratorParameters {
+            crossover_multiplier: Some(2.0),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgSingles, params);
         g.next_foot = Foot::Left;
         g.step(1);
@@ -1213,8 +1799,10 @@ fn steps_prob() {
     }
     // bar angle decay
     {
-        let mut params = GeneratorParameters::default();
-        params.bar_angle_decay = Some((0.3, 0.5));
+        let params = GeneratorParameters {
+            bar_angle_decay: Some((0.3, 0.5)),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgDoubles, params);
         g.next_foot = Foot::Right;
         g.step(4);
@@ -1225,8 +1813,10 @@ fn steps_prob() {
     }
     // preserve input repetitions different decay
     {
-        let mut params = GeneratorParameters::default();
-        params.preserve_input_repetitions = Some(0.5);
+        let params = GeneratorParameters {
+            preserve_input_repetitions: Some(0.5),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::ItgSingles, params);
         g.next_foot = Foot::Left;
         g.step_with_input_col(0, 4);
@@ -1238,8 +1828,10 @@ fn steps_prob() {
     }
     // doubles movement distance decay
     {
-        let mut params = GeneratorParameters::default();
-        params.doubles_movement = Some((1.0, 0.5));
+        let params = GeneratorParameters {
+            doubles_movement: Some((1.0, 0.5)),
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonDoubles, params);
         g.next_foot = Foot::Left;
         g.step(1);
@@ -1266,9 +1858,11 @@ fn steps_prob() {
     }
     // doubles movement distance decay
     {
-        let mut params = GeneratorParameters::default();
-        params.doubles_movement = Some((1.0, 0.5));
-        params.doubles_track_individual_feet = true;
+        let params = GeneratorParameters {
+            doubles_movement: Some((1.0, 0.5)),
+            doubles_track_individual_feet: true,
+            ..GeneratorParameters::default()
+        };
         let mut g = Generator::new(Style::HorizonDoubles, params);
         g.next_foot = Foot::Left;
         g.step(1);
@@ -0,0 +1,282 @@
+// Pads that aren't built in, loaded from a .toml/.json file so that charts can be generated for
+// them as Style::Custom.
+//
+// A layout file is a list of layouts, e.g. for a 6 panel pad written as pump-halfdouble charts:
+//
+//     [[layouts]]
+//     name = "hex-singles"
+//     stepstype = "pump-halfdouble"
+//     panels = [[0.0, 1.0], [1.0, 0.0], [1.0, 2.0], [2.0, 0.0], [2.0, 2.0], [3.0, 1.0]]
+//     start = [1, 4]
+
+use crate::coord::Coord;
+use crate::error::Error;
+use crate::style::Style;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::RwLock;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Layout {
+    // the name used with -i/-o
+    pub name: String,
+    // the STEPSTYPE of the charts written, e.g. "dance-single"
+    pub stepstype: String,
+    // the coordinates of each column's panel, with x from 0 at the left
+    pub panels: Vec<(f32, f32)>,
+    // the columns the left and right foot start on
+    pub start: (i8, i8),
+    // columns of 0s written on each side of the panels, e.g. 2 for 6 panels of a pump-double
+    #[serde(default)]
+    pub padding: usize,
+    // for pads where a foot steps on several columns at once, the columns written for each
+    // panel. Every panel is its own column if empty.
+    #[serde(default)]
+    pub brackets: Vec<Vec<i8>>,
+    // pairs of columns one foot can press at once with --hands brackets. Panels less than 1.5
+    // apart can if empty.
+    #[serde(default)]
+    pub bracketable: Vec<(i8, i8)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LayoutFile {
+    layouts: Vec<Layout>,
+}
+
+// A registered layout. Only register() and find() make them, so every one has a layout.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LayoutId(u8);
+
+// Registered layouts, indexed by LayoutId. Layouts are never removed, and live for the rest of
+// the program so that styles can stay Copy.
+static LAYOUTS: RwLock<Vec<&'static Layout>> = RwLock::new(Vec::new());
+
+impl Layout {
+    fn check(&self) -> Result<(), String> {
+        let num_cols = self.panels.len();
+        if num_cols == 0 || num_cols > i8::MAX as usize {
+            return Err(format!("{} panels", num_cols));
+        }
+        if self.stepstype.trim().is_empty() {
+            return Err("no stepstype".to_owned());
+        }
+        let is_col = |col: i8| col >= 0 && (col as usize) < num_cols;
+        if !is_col(self.start.0) || !is_col(self.start.1) {
+            return Err(format!("no panel {:?} to start on", self.start));
+        }
+        if self.panels.iter().any(|&(x, _)| x < 0.0) {
+            return Err("a panel left of x = 0".to_owned());
+        }
+        if !self.brackets.is_empty() {
+            if self.brackets.len() != num_cols {
+                return Err(format!(
+                    "{} bracket groups for {} panels",
+                    self.brackets.len(),
+                    num_cols
+                ));
+            }
+            if self
+                .brackets
+                .iter()
+                .any(|cols| cols.is_empty() || !cols.iter().all(|&c| is_col(c)))
+            {
+                return Err("a bracket group with no columns or a column that isn't one".to_owned());
+            }
+        }
+        if let Some(&(a, b)) = self
+            .bracketable
+            .iter()
+            .find(|&&(a, b)| a == b || !is_col(a) || !is_col(b))
+        {
+            return Err(format!("can't bracket columns {} and {}", a, b));
+        }
+        // rows have to be as wide as StepMania expects for the STEPSTYPE, where itggen knows it
+        let width = num_cols + 2 * self.padding;
+        if let Some(style) = Style::ALL
+            .into_iter()
+            .find(|s| s.is_sm_string(&self.stepstype))
+        {
+            let sm_width = style.num_cols() as usize + 2 * style.extra_0s();
+            if width != sm_width {
+                return Err(format!(
+                    "rows of {} columns, but {} rows have {}",
+                    width,
+                    style.sm_string(),
+                    sm_width
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn num_cols(&self) -> i8 {
+        self.panels.len() as i8
+    }
+
+    pub fn coord(&self, col: i8) -> Coord {
+        let (x, y) = self.panels[col as usize];
+        Coord(x, y)
+    }
+
+    pub fn max_x_coord(&self) -> f32 {
+        self.panels.iter().map(|&(x, _)| x).fold(0.0, f32::max)
+    }
+
+    // Whether one foot can press both columns, if the layout says which it can.
+    pub fn can_bracket(&self, col1: i8, col2: i8) -> Option<bool> {
+        if self.bracketable.is_empty() {
+            return None;
+        }
+        Some(
+            self.bracketable
+                .iter()
+                .any(|&(a, b)| (a, b) == (col1, col2) || (b, a) == (col1, col2)),
+        )
+    }
+
+    pub fn sm_cols_for_col(&self, col: i8) -> Vec<i8> {
+        match self.brackets.get(col as usize) {
+            Some(cols) => cols.clone(),
+            None => vec![col],
+        }
+    }
+}
+
+pub fn get(id: LayoutId) -> &'static Layout {
+    LAYOUTS.read().unwrap()[id.0 as usize]
+}
+
+// Returns the first registered layout matching f.
+pub fn find(f: impl Fn(&Layout) -> bool) -> Option<Style> {
+    LAYOUTS
+        .read()
+        .unwrap()
+        .iter()
+        .position(|l| f(l))
+        .map(|i| Style::Custom(LayoutId(i as u8)))
+}
+
+// Makes layout a style that can be generated from and for, and returns it.
+pub fn register(layout: Layout) -> Result<Style, Error> {
+    let invalid = |e: String| Error::Config(format!("Invalid layout '{}': {}", layout.name, e));
+    layout.check().map_err(invalid)?;
+    if layout.name == "auto" || layout.name.parse::<Style>().is_ok() {
+        return Err(invalid("there already is a style by that name".to_owned()));
+    }
+    let mut layouts = LAYOUTS.write().unwrap();
+    if layouts.len() > u8::MAX as usize {
+        return Err(invalid("too many layouts".to_owned()));
+    }
+    layouts.push(Box::leak(Box::new(layout)));
+    Ok(Style::Custom(LayoutId((layouts.len() - 1) as u8)))
+}
+
+// Parses a layout file as JSON if is_json, otherwise as TOML.
+pub fn parse(contents: &str, is_json: bool) -> Result<Vec<Layout>, Error> {
+    let file: LayoutFile = if is_json {
+        serde_json::from_str(contents).map_err(|e| Error::Config(e.to_string()))?
+    } else {
+        toml::from_str(contents).map_err(|e| Error::Config(e.to_string()))?
+    };
+    Ok(file.layouts)
+}
+
+// Registers every layout in the file at path, and returns their styles.
+pub fn load(path: &Path) -> Result<Vec<Style>, Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("Couldn't read layouts {:?}: {}", path, e)))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|e| e.to_string_lossy().to_lowercase() == "json");
+    let layouts = parse(&contents, is_json)
+        .map_err(|e| Error::Config(format!("Couldn't parse layouts {:?}: {}", path, e)))?;
+    layouts.into_iter().map(register).collect()
+}
+
+#[test]
+fn test_register() {
+    use crate::foot::Foot;
+    let layouts = parse(
+        "[[layouts]]\nname = \"test-hex\"\nstepstype = \"pump-double\"\npanels = [[0.0, 1.0], [1.0, 0.0], [1.0, 2.0], [2.0, 0.0], [2.0, 2.0], [3.0, 1.0]]\nstart = [1, 4]\npadding = 2\n",
+        false,
+    )
+    .unwrap();
+    let hex = register(layouts[0].clone()).unwrap();
+    assert_eq!(hex.name(), "test-hex");
+    assert_eq!("test-hex".parse::<Style>().unwrap(), hex);
+    assert_eq!(hex.num_cols(), 6);
+    assert_eq!(hex.extra_0s(), 2);
+    assert_eq!(hex.init_col(Foot::Right), 4);
+    assert_eq!(hex.max_x_coord(), 3.0);
+    assert_eq!(hex.coord(5), Coord(3.0, 1.0));
+    assert_eq!(hex.sm_cols_for_col(5), vec![5]);
+    assert!(hex.can_bracket(0, 1));
+    assert!(!hex.can_bracket(0, 5));
+    // built-in styles come first
+    assert_eq!(
+        Style::from_sm_string("pump-double"),
+        Some(Style::PumpDoubles)
+    );
+
+    let nine = parse(
+        "{\"layouts\": [{\"name\": \"test-nine\", \"stepstype\": \"test-nine\", \"panels\": [[0, 0], [0, 2], [2, 0], [2, 2]], \"start\": [0, 3], \"brackets\": [[0], [1], [2, 3], [3]]}]}",
+        true,
+    )
+    .unwrap();
+    let nine = register(nine[0].clone()).unwrap();
+    assert_eq!(Style::from_sm_string("TEST-NINE"), Some(nine));
+    assert_eq!(nine.sm_cols_for_col(2), vec![2, 3]);
+
+    let invalid = |change: fn(&mut Layout)| {
+        let mut layout = layouts[0].clone();
+        layout.name = "test-invalid".to_owned();
+        change(&mut layout);
+        register(layout).is_err()
+    };
+    assert!(invalid(|l| l.start = (1, 6)));
+    assert!(invalid(|l| l.panels.clear()));
+    assert!(invalid(|l| l.panels[0] = (-1.0, 1.0)));
+    assert!(invalid(|l| l.brackets = vec![vec![0]]));
+    assert!(invalid(|l| l.bracketable = vec![(0, 6)]));
+    assert!(invalid(|l| l.bracketable = vec![(2, 2)]));
+    // pump-double rows have 10 columns
+    assert!(invalid(|l| l.padding = 1));
+    assert!(invalid(|l| l.stepstype = "dance-double".to_owned()));
+
+    let mut wide = layouts[0].clone();
+    wide.name = "test-wide".to_owned();
+    wide.bracketable = vec![(5, 0)];
+    let wide = register(wide).unwrap();
+    assert!(wide.can_bracket(0, 5));
+    assert!(!wide.can_bracket(0, 1));
+    assert!(invalid(|l| l.name = "itg-singles".to_owned()));
+    assert!(invalid(|l| l.name = "test-hex".to_owned()));
+    assert!(parse("[[layouts]]\nname = \"a\"\ncols = 4\n", false).is_err());
+    assert!(load(Path::new("/nonexistent.toml")).is_err());
+
+    let chart = "#NOTES:\n dance-single:\n :\n Hard:\n 9:\n :\n1000\n0100\n0010\n0001\n;\n";
+    let generated = crate::sm::generate(
+        chart,
+        Style::ItgSingles,
+        hex,
+        crate::GeneratorParameters::default(),
+        &crate::preset::ParamCurves::default(),
+        crate::sm::DifficultyPolicy::Same,
+        None,
+        false,
+    )
+    .unwrap();
+    assert!(generated.contains("pump-double:"));
+    let rows: Vec<&str> = generated
+        .lines()
+        .filter(|l| l.starts_with(['0', '1']))
+        .collect();
+    assert_eq!(rows.len(), 4);
+    assert!(
+        rows.iter()
+            .all(|r| r.len() == 10 && r.starts_with("00") && r.ends_with("00"))
+    );
+}
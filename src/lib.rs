@@ -0,0 +1,63 @@
+// Generates charts for one dance game style from charts for another.
+//
+// msd tokenizes .sm/.ssc files, sm parses them into SMCharts and writes generated charts back
+// out, generator chooses the steps, and preset, sidecar and metadata hold the parameters that
+// drive it. layout adds pads that aren't built in.
+
+pub mod backup;
+mod beam;
+pub mod coord;
+pub mod diff;
+pub mod encoding;
+pub mod error;
+pub mod foot;
+pub mod generator;
+pub mod layout;
+pub mod metadata;
+pub mod mirror;
+pub mod msd;
+pub mod preset;
+pub mod sidecar;
+pub mod sm;
+pub mod style;
+pub mod timing;
+
+pub use error::{Error, ParseError, ParseErrorKind};
+pub use generator::{Generator, GeneratorParameters, HandsPolicy, Rule};
+pub use metadata::ChartMetadata;
+pub use sm::{DifficultyPolicy, GeneratedChart, SMChart, Skipped, SourcePolicy};
+pub use style::Style;
+
+#[test]
+fn test_public_api() {
+    let contents = "#BPMS:0=120;\n#NOTES:\n dance-single:\n :\n Hard:\n 9:\n :\n1000\n0100\n;\n";
+    let charts = sm::parse_charts(contents, false).unwrap();
+    assert_eq!(charts.len(), 1);
+    assert_eq!(charts[0].level, 9);
+    let timing = sm::song_timing(contents, false).unwrap();
+    let params = GeneratorParameters {
+        seed: Some(1),
+        ..GeneratorParameters::default()
+    };
+    let notes = sm::generate_notes(&charts[0], &timing, Style::ItgDoubles, params).unwrap();
+    assert_eq!(notes.notes.lines().count(), 3);
+    let hands = "#BPMS:0=120;\n#NOTES:\n dance-single:\n :\n Hard:\n 9:\n :\n1111\n;\n#NOTES:\n pump-single:\n :\n Hard:\n 9:\n :\n10000\n;\n";
+    let (charts, skipped) = sm::generate_charts(
+        hands,
+        Style::ItgSingles,
+        Style::ItgDoubles,
+        params,
+        &preset::ParamCurves::default(),
+        DifficultyPolicy::Same,
+        None,
+        false,
+    )
+    .unwrap();
+    assert_eq!(charts[0].dropped, 2);
+    assert!(charts[0].relaxations.is_empty());
+    assert_eq!(skipped, vec![Skipped::Style("pump-single".to_owned())]);
+    assert!(matches!(
+        sm::parse_charts("#NOTES:\n dance-single:\n", false),
+        Err(Error::Parse(_))
+    ));
+}
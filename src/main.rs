@@ -1,30 +1,103 @@
-mod coord;
-mod foot;
-mod generator;
-mod sm;
-mod style;
-
-use generator::GeneratorParameters;
-use std::f32::consts::PI;
+use encoding_rs::Encoding;
+use itggen::preset::{self, create_params};
+use itggen::style::StyleParseError;
+use itggen::{
+    DifficultyPolicy, Error, GeneratorParameters, HandsPolicy, SourcePolicy, Style, backup, diff,
+    encoding, layout, metadata, mirror, sidecar, sm,
+};
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use structopt::StructOpt;
-use style::Style;
 
+// Where to find simfiles and how to read them, shared by every command.
 #[derive(Debug, StructOpt)]
-#[structopt(name = "itggen")]
-struct Opts {
+struct InputOpts {
     #[structopt(
         parse(from_os_str),
         min_values = 1,
-        help = "Paths of/directories containing .sm files to generate charts"
+        help = "Paths of/directories containing .sm/.ssc files"
     )]
     inputs: Vec<PathBuf>,
 
+    #[structopt(
+        long = "encoding",
+        parse(try_from_str = encoding::from_label),
+        help = "Encoding of the input files (e.g. 'shift_jis', 'cp1252'), detected if not given"
+    )]
+    encoding: Option<&'static Encoding>,
+}
+
+// Options of the commands that change simfiles.
+#[derive(Debug, StructOpt)]
+struct WriteOpts {
+    #[structopt(flatten)]
+    input: InputOpts,
+
+    #[structopt(
+        long = "output-dir",
+        parse(from_os_str),
+        help = "Write to a copy of each input directory here instead of changing it"
+    )]
+    output_dir: Option<PathBuf>,
+
+    #[structopt(
+        long = "hardlink",
+        requires = "output-dir",
+        help = "Hardlink audio, graphics etc. into --output-dir instead of copying them"
+    )]
+    hardlink: bool,
+
+    #[structopt(
+        short,
+        help = "Dry run (don't actually write to disk, list the charts that would be added and removed instead)"
+    )]
+    dry_run: bool,
+
+    #[structopt(
+        long = "diff",
+        requires = "dry-run",
+        help = "Show a unified diff of each file on dry runs"
+    )]
+    diff: bool,
+
+    #[structopt(
+        long = "backup-dir",
+        parse(from_os_str),
+        help = "Keep backups of changed files here instead of next to them"
+    )]
+    backup_dir: Option<PathBuf>,
+
+    #[structopt(
+        long = "sync-sm",
+        help = "Also change the .sm of songs that have an .ssc (StepMania 5 only loads the .ssc)"
+    )]
+    sync_sm: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct GenerateOpts {
+    #[structopt(flatten)]
+    write: WriteOpts,
+
     #[structopt(long = "seed", help = "Seed for rng")]
     seed: Option<u64>,
 
-    #[structopt(short = "i", help = "Style to base charts off of (e.g. 'itg-singles')")]
-    from_style: Style,
+    #[structopt(
+        short = "i",
+        required = true,
+        number_of_values = 1,
+        use_delimiter = true,
+        help = "Style(s) to base charts off of (e.g. 'itg-singles,pump-singles'), see --source. 'auto' is the styles of the song's own charts, generating only the -o styles it has no charts of"
+    )]
+    from_style: Vec<FromStyle>,
+
+    #[structopt(
+        long = "source",
+        default_value = "first-style",
+        help = "Which charts of the -i styles to base charts off of: all charts of the first style the song has (first-style), the chart of each difficulty from the first style that has one (same-difficulty), or the first style's charts with the difficulties it is missing filled in from the other styles by meter (closest-meter)"
+    )]
+    source: SourcePolicy,
 
     #[structopt(
         short = "o",
@@ -34,7 +107,10 @@ struct Opts {
     )]
     to_style: Vec<Style>,
 
-    #[structopt(short, help = "Remove existing autogen charts before generating")]
+    #[structopt(
+        short,
+        help = "Remove autogen charts that aren't generated again (the rest are always replaced where they are)"
+    )]
     remove_existing_autogen: bool,
 
     #[structopt(short, help = "Preserve arrow jacks/changes from input chart")]
@@ -56,6 +132,24 @@ struct Opts {
     #[structopt(long = "vroom", help = "Move more on doubles")]
     vroom: bool,
 
+    #[structopt(
+        long = "hands",
+        help = "What to do with rows of 3+ notes (jumps, brackets, hands) [default: jumps]"
+    )]
+    hands: Option<HandsPolicy>,
+
+    #[structopt(
+        long = "beam",
+        help = "Search this many candidate charts at once instead of choosing each step independently"
+    )]
+    beam_width: Option<usize>,
+
+    #[structopt(
+        long = "preset",
+        help = "Generator parameters to use, either a built-in preset (default, crossovers, hard-crossovers, easy-crossovers, vroom) or a .toml/.json file setting any parameter"
+    )]
+    preset: Option<String>,
+
     #[structopt(short, help = "Allow footswitches")]
     footswitches: bool,
 
@@ -68,225 +162,1055 @@ struct Opts {
     #[structopt(short, help = "Create autogen charts as edits")]
     edits: bool,
 
+    #[structopt(
+        long = "fill-gaps",
+        help = "Only generate difficulties the target style has no chart for yet (with -e, generate edits for the others)"
+    )]
+    fill_gaps: bool,
+
     #[structopt(short = "x", help = "Extra string to add to description")]
     extra_description: Option<String>,
+}
 
-    #[structopt(short, help = "Dry run (don't actually write to disk)")]
+// A style given with -i.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum FromStyle {
+    Style(Style),
+    // the styles of the charts in each song that itggen didn't write
+    Auto,
+}
+
+impl FromStr for FromStyle {
+    type Err = StyleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(FromStyle::Auto),
+            _ => s.parse().map(FromStyle::Style),
+        }
+    }
+}
+
+// Returns the styles to generate from for a song with the given charts, with 'auto' replaced by
+// the styles of its charts that aren't autogen, in the order they're in the file.
+fn from_styles(from_style: &[FromStyle], charts: &[sm::SMChart]) -> Vec<Style> {
+    let mut ret = Vec::new();
+    for f in from_style {
+        let styles = match f {
+            FromStyle::Style(style) => vec![*style],
+            FromStyle::Auto => charts
+                .iter()
+                .filter(|c| !c.is_autogen())
+                .filter_map(|c| Style::from_sm_string(&c.style))
+                .collect(),
+        };
+        for style in styles {
+            if !ret.contains(&style) {
+                ret.push(style);
+            }
+        }
+    }
+    ret
+}
+
+#[derive(Debug, StructOpt)]
+struct RestoreOpts {
+    #[structopt(flatten)]
+    input: InputOpts,
+
+    #[structopt(
+        short,
+        help = "Dry run (only print which backup each file would be restored from)"
+    )]
     dry_run: bool,
+
+    #[structopt(
+        long = "backup-dir",
+        parse(from_os_str),
+        help = "Where the backups were kept, if not next to the files"
+    )]
+    backup_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "itggen")]
+struct Opt {
+    // only here for --help and so that --layout is accepted, see layout_paths()
+    #[allow(dead_code)]
+    #[structopt(
+        long = "layout",
+        global = true,
+        number_of_values = 1,
+        parse(from_os_str),
+        help = "A .toml/.json file of pads that aren't built in, which -i and -o can then name"
+    )]
+    layouts: Vec<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(about = "Generate charts for other styles from the charts of one style")]
+    Generate(GenerateOpts),
+    #[structopt(
+        about = "Regenerate the charts recorded in each file's .itggen.json, e.g. after updating itggen"
+    )]
+    Regenerate(WriteOpts),
+    #[structopt(about = "Remove autogen charts without generating new ones")]
+    Strip(WriteOpts),
+    #[structopt(about = "List every chart's style, difficulty, meter and description")]
+    List(InputOpts),
+    #[structopt(about = "Count the steps, jumps, holds etc. of every chart")]
+    Analyze(InputOpts),
+    #[structopt(about = "Check that every file can be read, parsed and generated from")]
+    Validate(InputOpts),
+    #[structopt(
+        about = "Restore each file to its oldest backup, from before anything was generated for it"
+    )]
+    Restore(RestoreOpts),
+}
+
+// Returns the --layout files in args.
+//
+// structopt turns -i/-o into Styles while it parses the command line, and fails on names it
+// doesn't know, so the styles of layouts have to be registered before it runs. This looks for
+// --layout the way clap would: as "--layout <path>" or "--layout=<path>", anywhere before a "--".
+fn layout_paths(args: &[OsString]) -> Vec<PathBuf> {
+    let mut ret = Vec::new();
+    let mut args = args.iter().map(|a| a.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--layout" {
+            ret.extend(args.next().map(|p| PathBuf::from(p.as_ref())));
+        } else if let Some(p) = arg.strip_prefix("--layout=") {
+            ret.push(PathBuf::from(p));
+        }
+    }
+    ret
 }
 
-fn sm_ssc_files(path: &Path) -> Vec<(PathBuf, bool)> {
-    let rd = match std::fs::read_dir(path) {
-        Ok(rd) => rd,
+// Returns the simfiles StepMania loads from every song folder under path: the .ssc if the folder
+// has one, otherwise the .sm. With sync_sm, folders with both also return the .sm, after the
+// .ssc.
+fn sm_ssc_files(path: &Path, sync_sm: bool) -> Vec<(PathBuf, bool)> {
+    let mut entries: Vec<_> = match std::fs::read_dir(path) {
+        Ok(rd) => rd.flatten().collect(),
         Err(_) => {
             return vec![];
         }
     };
-    let mut ret = Vec::new();
-    for de in rd.flatten() {
+    entries.sort_by_key(|de| de.path());
+    let mut sm = Vec::new();
+    let mut ssc = Vec::new();
+    let mut subdirs = Vec::new();
+    for de in entries {
         if let Ok(t) = de.file_type() {
             if t.is_dir() {
-                ret.append(&mut sm_ssc_files(&de.path()));
+                subdirs.append(&mut sm_ssc_files(&de.path(), sync_sm));
             } else if t.is_file() {
                 let p = de.path();
                 if let Some(Some(ext)) = p.extension().map(|e| e.to_str()) {
                     if ext.to_lowercase() == "sm" {
-                        ret.push((de.path(), false));
+                        sm.push((p, false));
                     } else if ext.to_lowercase() == "ssc" {
-                        ret.push((de.path(), true));
+                        ssc.push((p, true));
                     }
                 }
             }
         }
     }
+    let mut ret = ssc;
+    if ret.is_empty() || sync_sm {
+        ret.append(&mut sm);
+    }
+    ret.append(&mut subdirs);
     ret
 }
 
-fn create_params(
-    seed: Option<u64>,
+#[test]
+fn test_sm_ssc_files() {
+    let dir = std::env::temp_dir().join(format!("itggen-files-{}", std::process::id()));
+    for f in ["a/a.sm", "a/a.ssc", "b/b.SM", "b/b.ogg", "b/c/c.ssc"] {
+        let p = dir.join(f);
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        std::fs::write(p, "").unwrap();
+    }
+    let files = |sync_sm| {
+        sm_ssc_files(&dir, sync_sm)
+            .into_iter()
+            .map(|(p, is_ssc)| (p.strip_prefix(&dir).unwrap().to_owned(), is_ssc))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        files(false),
+        vec![
+            (PathBuf::from("a/a.ssc"), true),
+            (PathBuf::from("b/b.SM"), false),
+            (PathBuf::from("b/c/c.ssc"), true),
+        ]
+    );
+    assert_eq!(files(true).len(), 4);
+    assert_eq!(files(true)[1], (PathBuf::from("a/a.sm"), false));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Returns (input, output, is_ssc) for every simfile in inputs, see sm_ssc_files(). Outputs are in
+// output_dir if given, otherwise they are the inputs.
+fn input_files(
+    inputs: &[PathBuf],
+    output_dir: Option<&Path>,
+    sync_sm: bool,
+) -> Vec<(PathBuf, PathBuf, bool)> {
+    let mut ret = Vec::new();
+    for input in inputs {
+        for (p, is_ssc) in sm_ssc_files(input, sync_sm) {
+            let out = match output_dir {
+                Some(dir) => mirror::output_path(input, &p, dir),
+                None => p.clone(),
+            };
+            ret.push((p, out, is_ssc));
+        }
+    }
+    if ret.is_empty() {
+        println!("no input files...");
+    }
+    ret
+}
+
+// What generator parameters are picked from: the flags and preset in opts, with the flags a
+// song's itggen.toml sets replaced.
+#[derive(Clone, Debug)]
+struct ParamChoices {
     crossovers: i32,
     more_easy_crossovers: bool,
     vroom: bool,
-    preserve_input_repetitions: bool,
-    disallow_footswitch: bool,
-    min_difficulty: Option<i32>,
-    max_difficulty: Option<i32>,
-) -> GeneratorParameters {
-    let has_crossovers = crossovers != 0;
-    GeneratorParameters {
-        seed,
-        disallow_footswitch,
-        max_repeated: None,
-        repeated_decay: if preserve_input_repetitions {
-            None
-        } else {
-            Some((1, 0.1))
-        },
-        other_foot_repeat_decay: if preserve_input_repetitions {
-            None
-        } else {
-            Some(0.3)
-        },
-        max_dist_between_feet: Some(2.9),
-        max_dist_between_feet_if_crossover: Some(2.5),
-        dist_between_feet_decay: None,
-        max_dist_between_steps: Some(if has_crossovers || vroom { 2.9 } else { 2.1 }),
-        dist_between_steps_decay: Some((2.5, 0.3)),
-        max_horizontal_dist_between_steps: if has_crossovers || vroom {
-            None
-        } else {
-            Some(1.0)
-        },
-        horizontal_dist_between_steps_decay: None,
-        max_horizontal_dist_between_steps_if_crossover: if more_easy_crossovers {
-            Some(1.9)
-        } else {
-            None
-        },
-        max_vertical_dist_between_steps: None,
-        vertical_dist_between_steps_decay: None,
-        horizontal_dist_between_3_steps_same_foot_decay: None,
-        max_horizontal_dist_between_4_steps_both_feet: if has_crossovers || vroom {
-            None
-        } else {
-            Some(2.5)
-        },
-        max_horizontal_dist_between_3_steps_same_foot: if has_crossovers || vroom {
-            None
-        } else {
-            Some(1.5)
-        },
-        max_angle: Some(PI * (0.5 + 0.3 * (crossovers as f32))),
-        angle_decay: None,
-        max_turn: Some(if crossovers > 1 { PI } else { PI * 3.0 / 4.0 }),
-        turn_decay: None,
-        crossover_multiplier: if more_easy_crossovers {
-            Some(2.0)
-        } else {
+    overrides: preset::Overrides,
+    curves: preset::ParamCurves,
+}
+
+fn choices_from_opts(opts: &GenerateOpts) -> Result<ParamChoices, Error> {
+    let mut ret = ParamChoices {
+        crossovers: opts.crossovers,
+        more_easy_crossovers: opts.more_easy_crossovers,
+        vroom: opts.vroom,
+        overrides: preset::Overrides::new(),
+        curves: preset::ParamCurves::default(),
+    };
+    if let Some(name) = &opts.preset {
+        match preset::builtin(name) {
+            // flags that are given win over the preset
+            Some(b) => {
+                if opts.crossovers == 0 {
+                    ret.crossovers = b.crossovers;
+                }
+                ret.more_easy_crossovers |= b.more_easy_crossovers;
+                ret.vroom |= b.vroom;
+            }
+            None => (ret.overrides, ret.curves) = preset::load(Path::new(name))?,
+        }
+    }
+    Ok(ret)
+}
+
+fn params_from_choices(
+    opts: &GenerateOpts,
+    choices: &ParamChoices,
+) -> Result<GeneratorParameters, Error> {
+    let params = GeneratorParameters {
+        beam_width: opts.beam_width,
+        ..create_params(
+            opts.seed,
+            choices.crossovers,
+            choices.more_easy_crossovers,
+            choices.vroom,
+            opts.preserve_input_repetitions,
+            !opts.footswitches,
+            opts.hands.unwrap_or_default(),
+            opts.min_difficulty,
+            opts.max_difficulty,
+        )
+    };
+    let no_flags = create_params(
+        None,
+        0,
+        false,
+        false,
+        false,
+        true,
+        HandsPolicy::default(),
+        None,
+        None,
+    );
+    // flags that are given win over a preset file, like over a built-in preset: the fields
+    // they set differently from no flags are set again after it, and --hands even if it is
+    // the default
+    let mut flags = preset::diff(params, no_flags)?;
+    if let Some(hands) = opts.hands {
+        flags.insert("hands".to_owned(), serde_json::json!(hands));
+    }
+    preset::apply(preset::apply(params, &choices.overrides)?, &flags)
+}
+
+// The parameters and parameter curves picked by the flags and preset in opts.
+#[cfg(test)]
+fn params_from_opts(
+    opts: &GenerateOpts,
+) -> Result<(GeneratorParameters, preset::ParamCurves), Error> {
+    let choices = choices_from_opts(opts)?;
+    Ok((params_from_choices(opts, &choices)?, choices.curves))
+}
+
+// The settings for a song with the itggen.toml settings s: (to_styles, params, curves, edits).
+fn song_settings(
+    opts: &GenerateOpts,
+    choices: &ParamChoices,
+    s: &sidecar::Sidecar,
+) -> Result<(Vec<Style>, GeneratorParameters, preset::ParamCurves, bool), Error> {
+    let choices = ParamChoices {
+        crossovers: s.crossovers.unwrap_or(choices.crossovers),
+        more_easy_crossovers: s
+            .more_easy_crossovers
+            .unwrap_or(choices.more_easy_crossovers),
+        vroom: s.vroom.unwrap_or(choices.vroom),
+        ..choices.clone()
+    };
+    Ok((
+        s.to_styles(&opts.to_style)?,
+        s.apply(params_from_choices(opts, &choices)?)?,
+        s.apply_curves(&choices.curves),
+        s.edits(opts.edits),
+    ))
+}
+
+#[test]
+fn test_song_settings() {
+    let opts =
+        GenerateOpts::from_iter(["generate", "-i", "itg-singles", "-o", "itg-doubles", "-c"]);
+    let choices = choices_from_opts(&opts).unwrap();
+    let params = |sidecar: &str| {
+        let s = sidecar::Sidecar::parse(sidecar).unwrap();
+        serde_json::to_value(song_settings(&opts, &choices, &s).unwrap().1).unwrap()
+    };
+    let no_crossovers = GenerateOpts::from_iter(["generate", "-i", "itg-singles"]);
+    assert_eq!(
+        params("crossovers = 0"),
+        serde_json::to_value(params_from_opts(&no_crossovers).unwrap().0).unwrap()
+    );
+    assert_eq!(
+        params(""),
+        serde_json::to_value(params_from_opts(&opts).unwrap().0).unwrap()
+    );
+    // [params] changes what the flags picked
+    assert_eq!(
+        params("crossovers = 0\n[params]\nmax_turn = 1.0")["max_turn"],
+        1.0
+    );
+}
+
+// Formats e like a compiler error so editors can jump to it, e.g.
+// "Songs/a/a.sm:12: error: unknown note 'x' (chart 2)".
+fn format_error(path: &Path, e: &Error) -> String {
+    match e {
+        Error::Parse(p) => {
+            let mut ret = path.display().to_string();
+            if let Some(line) = p.line {
+                ret.push_str(&format!(":{}", line));
+            }
+            ret.push_str(&format!(": error: {}", p.kind));
+            if let Some(chart) = p.chart {
+                ret.push_str(&format!(" (chart {})", chart + 1));
+            }
+            ret
+        }
+        _ => format!("{}: error: {}", path.display(), e),
+    }
+}
+
+// Reads the simfile at p, printing why it was skipped if it can't be read.
+fn read_simfile(input: &InputOpts, p: &Path) -> Option<(String, &'static Encoding)> {
+    match encoding::read(p, input.encoding) {
+        Ok(s) => Some(s),
+        Err(e) => {
+            println!("  couldn't read file: {}", e);
             None
-        },
-        max_bar_angle: None,
-        bar_angle_decay: Some((0.0, if has_crossovers || vroom { 0.4 } else { 0.9 })),
-        preserve_input_repetitions: if preserve_input_repetitions {
-            Some(0.01)
+        }
+    }
+}
+
+// Copies every input to --output-dir, if given, before any simfile is written into the copy:
+// the whole pack for a directory, with its banner, Group.ini and songs that don't parse, or
+// the song folder of a single simfile, matching where input_files() puts the outputs.
+fn mirror_inputs(opts: &WriteOpts) -> Result<(), Error> {
+    let Some(output_dir) = opts.output_dir.as_deref() else {
+        return Ok(());
+    };
+    if opts.dry_run {
+        return Ok(());
+    }
+    for input in &opts.input.inputs {
+        let src_dir = if input.is_dir() {
+            input.as_path()
         } else {
-            None
-        },
-        doubles_movement: Some((0.5, 0.002)),
-        doubles_dist_from_side: if vroom { Some(0.0) } else { None },
-        doubles_steps_per_dist: if vroom { Some(2.5) } else { None },
-        doubles_track_individual_feet: !vroom && !has_crossovers,
-        disallow_foot_opposite_side: !has_crossovers,
-        remove_jumps: has_crossovers,
-        min_difficulty,
-        max_difficulty,
+            match input.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            }
+        };
+        mirror::copy_dir(src_dir, output_dir, opts.hardlink)?;
     }
+    Ok(())
 }
 
-fn main() -> std::io::Result<()> {
-    let opts = Opts::from_args();
+#[test]
+fn test_mirror_inputs() {
+    let dir = std::env::temp_dir().join(format!("itggen-mirror-inputs-{}", std::process::id()));
+    let pack = dir.join("pack");
+    std::fs::create_dir_all(pack.join("broken")).unwrap();
+    std::fs::create_dir_all(pack.join("song")).unwrap();
+    std::fs::write(pack.join("Group.ini"), "[Group]").unwrap();
+    std::fs::write(pack.join("broken/broken.sm"), "#NOTES:x").unwrap();
+    std::fs::write(pack.join("song/song.sm"), "#TITLE:a;").unwrap();
+    let out = dir.join("out");
+    let opts = |args: &[&str]| {
+        WriteOpts::from_iter(
+            [
+                &["strip", "--output-dir", out.to_str().unwrap()],
+                args,
+                &[pack.to_str().unwrap()],
+            ]
+            .concat(),
+        )
+    };
+    mirror_inputs(&opts(&["-d"])).unwrap();
+    assert!(!out.exists());
+    mirror_inputs(&opts(&["--hardlink"])).unwrap();
+    for f in ["Group.ini", "broken/broken.sm", "song/song.sm"] {
+        assert!(out.join(f).exists(), "{}", f);
+    }
+    let song_out = dir.join("song out");
+    let opts = WriteOpts::from_iter([
+        "strip",
+        "--output-dir",
+        song_out.to_str().unwrap(),
+        pack.join("song/song.sm").to_str().unwrap(),
+    ]);
+    mirror_inputs(&opts).unwrap();
+    assert!(song_out.join("song.sm").exists());
+    assert!(!song_out.join("Group.ini").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
 
-    let files: Vec<(PathBuf, bool)> = opts.inputs.iter().flat_map(|i| sm_ssc_files(i)).collect();
+// Writes contents, generated from the simfile at p, to out. p is backed up first if out is p,
+// otherwise out is in the copy made by mirror_inputs().
+fn write_simfile(
+    opts: &WriteOpts,
+    p: &Path,
+    out: &Path,
+    contents: &str,
+    encoding: &'static Encoding,
+) -> Result<(), Error> {
+    if p == out {
+        backup::backup(p, opts.backup_dir.as_deref())?;
+    }
+    encoding::write(out, contents, encoding)
+}
 
-    if files.is_empty() {
-        println!("no input files...");
+// Prints what writing new over old, the contents of the file at p, would change.
+fn print_changes(opts: &WriteOpts, p: &Path, old: &str, new: &str, is_ssc: bool) {
+    if opts.diff {
+        print!("{}", diff::unified(&p.display().to_string(), old, new));
+        return;
+    }
+    match diff::chart_changes(old, new, is_ssc) {
+        Ok(changes) if changes.is_empty() => println!("  no changes"),
+        Ok(changes) => {
+            for c in changes {
+                println!("  {}", c);
+            }
+        }
+        Err(e) => println!("  {}", format_error(p, &e)),
     }
+}
 
-    for (p, is_ssc) in files {
-        println!("generating for {:?}", p);
-        let mut contents = match std::fs::read_to_string(p.clone()) {
-            Ok(s) => s,
-            Err(e) => {
-                println!("  couldn't read file: {}", e);
+// Prints what chart was generated, with its relaxed rules and the notes dropped.
+fn print_generated(chart: &sm::GeneratedChart, several_sources: bool) {
+    for (line, rule) in &chart.relaxations {
+        println!("  warning: line {}: relaxed {}", line, rule);
+    }
+    let m = &chart.metadata;
+    let generated_for = match &m.to_difficulty {
+        Some(d) => format!(
+            "{} from {} {} {} '{}'",
+            d,
+            m.from_style.sm_string(),
+            m.from_difficulty,
+            m.from_level,
+            m.from_description
+        ),
+        None if several_sources => {
+            format!("{} from {}", m.from_difficulty, m.from_style.sm_string())
+        }
+        None => m.from_difficulty.clone(),
+    };
+    if chart.dropped > 0 {
+        println!(
+            "  generated for {} ({} notes of jumps and hands dropped)",
+            generated_for, chart.dropped
+        );
+    } else {
+        println!("  generated for {}", generated_for);
+    }
+}
+
+// Generates charts from the simfile at p, writing the result to out.
+fn generate(
+    opts: &GenerateOpts,
+    choices: &ParamChoices,
+    p: &Path,
+    out: &Path,
+    is_ssc: bool,
+) -> Result<(), Error> {
+    println!("generating for {:?}", p);
+    let Some((contents, encoding)) = read_simfile(&opts.write.input, p) else {
+        return Ok(());
+    };
+    let settings = sidecar::Sidecar::for_song(p).and_then(|s| song_settings(opts, choices, &s));
+    let (to_styles, params, curves, edits) = match settings {
+        Ok(s) => s,
+        Err(e) => {
+            println!("  skipped: {}", e);
+            return Ok(());
+        }
+    };
+    let chart_metadata = metadata::load(p).unwrap_or_else(|e| {
+        println!("  warning: ignoring {}", e);
+        Vec::new()
+    });
+    let difficulties = match (opts.fill_gaps, edits) {
+        (false, false) => DifficultyPolicy::Same,
+        (false, true) => DifficultyPolicy::Edit,
+        (true, false) => DifficultyPolicy::FillGaps,
+        (true, true) => DifficultyPolicy::FillGapsOrEdit,
+    };
+    let charts = match sm::parse_charts(&contents, is_ssc) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("  skipped: {}", format_error(p, &e));
+            return Ok(());
+        }
+    };
+    let auto = opts.from_style.contains(&FromStyle::Auto);
+    let mut generated = Vec::new();
+    for to_style in &to_styles {
+        let mut from_styles = from_styles(&opts.from_style, &charts);
+        if auto {
+            // charts aren't generated from charts of the same style
+            from_styles.retain(|s| s.sm_string() != to_style.sm_string());
+            let has_style = charts
+                .iter()
+                .any(|c| !c.is_autogen() && to_style.is_sm_string(&c.style));
+            if has_style && difficulties == DifficultyPolicy::Same {
+                println!(
+                    "  skipping {}, the song already has {} charts",
+                    to_style.name(),
+                    to_style.sm_string()
+                );
                 continue;
             }
-        };
-        if opts.remove_existing_autogen {
-            contents = sm::remove_existing_autogen(&contents, is_ssc);
         }
-        let mut generated = String::new();
-        for to_style in &opts.to_style {
-            let params = create_params(
-                opts.seed,
-                opts.crossovers,
-                opts.more_easy_crossovers,
-                opts.vroom,
-                opts.preserve_input_repetitions,
-                !opts.footswitches,
-                opts.min_difficulty,
-                opts.max_difficulty,
-            );
-
-            println!("  {:?} -> {:?}", opts.from_style, to_style);
-            match sm::generate(
-                &contents,
-                opts.from_style,
-                *to_style,
-                params,
-                opts.edits,
-                opts.extra_description.as_ref(),
-                is_ssc,
-            ) {
-                Ok(s) => {
-                    generated.push('\n');
-                    generated.push_str(&s);
+        let names: Vec<&str> = from_styles.iter().map(|s| s.name()).collect();
+        println!(
+            "  {} -> {} (hands: {})",
+            names.join("/"),
+            to_style.name(),
+            params.hands
+        );
+        match sm::generate_charts_from_styles(
+            &contents,
+            &from_styles,
+            opts.source,
+            *to_style,
+            params,
+            &curves,
+            difficulties,
+            opts.extra_description.as_ref(),
+            is_ssc,
+        ) {
+            Ok((charts, skipped)) => {
+                for s in skipped {
+                    println!("  {}", s);
                 }
-                Err(e) => {
-                    println!("  skipped: {}", e);
+                for c in &charts {
+                    print_generated(c, from_styles.len() > 1);
                 }
+                generated.extend(charts);
+            }
+            Err(e) => {
+                println!("  skipped: {}", format_error(p, &e));
             }
         }
-        contents.push_str(&generated);
-        if opts.dry_run {
-            println!("  done (dry run)");
-        } else {
-            std::fs::write(p.clone(), contents)?;
-            println!("  done");
+    }
+    let written = sm::write_generated(
+        &contents,
+        chart_metadata,
+        generated,
+        opts.remove_existing_autogen,
+        is_ssc,
+    );
+    let (new_contents, chart_metadata) = match written {
+        Ok(w) => w,
+        Err(e) => {
+            println!("  skipped: {}", format_error(p, &e));
+            return Ok(());
         }
+    };
+    if opts.write.dry_run {
+        print_changes(&opts.write, p, &contents, &new_contents, is_ssc);
+        println!("  done (dry run)");
+    } else if let Err(e) = write_simfile(&opts.write, p, out, &new_contents, encoding) {
+        println!("  skipped: {}", format_error(p, &e));
+    } else if let Err(e) = metadata::save(out, &chart_metadata) {
+        println!(
+            "  skipped: couldn't write {:?}: {}",
+            metadata::path_for(out),
+            e
+        );
+    } else {
+        println!("  done");
     }
-
     Ok(())
 }
 
-#[test]
-fn test_params() {
-    use rand::prelude::*;
-    let mut chart =
-        "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n"
-            .to_owned();
-    let mut rng = StdRng::from_os_rng();
-    for _ in 0..500 {
-        chart.push_str(match rng.random_range(0..4) {
-            0 => "1000",
-            1 => "0100",
-            2 => "0010",
-            3 => "0001",
-            _ => panic!(),
-        });
-        chart.push('\n');
-    }
-    chart.push(';');
-    for to_style in [Style::ItgDoubles, Style::PumpSingles, Style::PumpDoubles] {
-        let check_params = |params: GeneratorParameters| {
-            let g = sm::generate(
-                &chart,
-                Style::ItgSingles,
-                to_style,
-                params,
-                false,
-                None,
-                false,
+// Regenerates every chart recorded in the metadata of the file at p, writing the result to out.
+fn regenerate(opts: &WriteOpts, p: &Path, out: &Path, is_ssc: bool) -> Result<(), Error> {
+    println!("regenerating for {:?}", p);
+    let Some((mut contents, encoding)) = read_simfile(&opts.input, p) else {
+        return Ok(());
+    };
+    let chart_metadata = match metadata::load(p) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("  skipped: {}", e);
+            return Ok(());
+        }
+    };
+    if chart_metadata.is_empty() {
+        println!("  nothing to regenerate");
+        return Ok(());
+    }
+    let original = contents.clone();
+    let mut changed = false;
+    for m in &chart_metadata {
+        println!(
+            "  {} {} -> {}",
+            m.from_style.name(),
+            m.from_difficulty,
+            m.to_style.name()
+        );
+        if m.version != metadata::VERSION {
+            println!(
+                "  warning: generated by itggen {}, this is {}",
+                m.version,
+                metadata::VERSION
             );
-            assert!(g.is_ok());
+        }
+        let chart = match sm::regenerate_chart(&contents, m, is_ssc) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("  skipped: {}", format_error(p, &e));
+                continue;
+            }
         };
-        for crossovers in 0..=2 {
-            for preserve in [false, true] {
-                check_params(create_params(
-                    None, crossovers, false, false, preserve, true, None, None,
-                ));
+        match sm::write_generated(&contents, Vec::new(), vec![chart], false, is_ssc) {
+            Ok((c, _)) if c == contents => println!("  unchanged"),
+            Ok((c, _)) => {
+                contents = c;
+                changed = true;
+                println!("  regenerated (changed)");
             }
+            Err(e) => println!("  skipped: {}", format_error(p, &e)),
         }
-        check_params(create_params(None, 1, true, false, false, true, None, None));
-        check_params(create_params(None, 0, false, true, false, true, None, None));
     }
+    // the copy in --output-dir is written either way
+    if !changed && p == out {
+        println!("  done (nothing changed)");
+    } else if opts.dry_run {
+        print_changes(opts, p, &original, &contents, is_ssc);
+        println!("  done (dry run)");
+    } else if let Err(e) = write_simfile(opts, p, out, &contents, encoding) {
+        println!("  skipped: {}", format_error(p, &e));
+    } else {
+        println!("  done");
+    }
+    Ok(())
+}
+
+// Removes the autogen charts from the file at p, writing the result to out.
+fn strip(opts: &WriteOpts, p: &Path, out: &Path, is_ssc: bool) -> Result<(), Error> {
+    println!("stripping {:?}", p);
+    let Some((contents, encoding)) = read_simfile(&opts.input, p) else {
+        return Ok(());
+    };
+    // every chart with metadata was autogen, so the metadata goes with them
+    let stripped = sm::remove_existing_autogen(&contents, is_ssc);
+    // the copy in --output-dir is written either way
+    if stripped == contents && p == out {
+        println!("  done (no autogen charts)");
+    } else if opts.dry_run {
+        print_changes(opts, p, &contents, &stripped, is_ssc);
+        println!("  done (dry run)");
+    } else if let Err(e) = write_simfile(opts, p, out, &stripped, encoding) {
+        println!("  skipped: {}", format_error(p, &e));
+    } else if let Err(e) = metadata::save(out, &[]) {
+        println!(
+            "  skipped: couldn't write {:?}: {}",
+            metadata::path_for(out),
+            e
+        );
+    } else {
+        println!("  done");
+    }
+    Ok(())
+}
+
+fn list(opts: &InputOpts, p: &Path, is_ssc: bool) {
+    println!("charts in {:?}", p);
+    let Some((contents, _)) = read_simfile(opts, p) else {
+        return;
+    };
+    match sm::parse_charts(&contents, is_ssc) {
+        Ok(charts) => {
+            for c in charts {
+                let autogen = if c.is_autogen() { " (autogen)" } else { "" };
+                println!("  {}{}", c.summary(), autogen);
+            }
+        }
+        Err(e) => println!("  skipped: {}", format_error(p, &e)),
+    }
+}
+
+// e.g. "412 steps, 38 jumps, 2 hands, 12 holds, 0 rolls, 20 mines in 64 measures, 1:52 long
+// (3.7 steps/s)"
+fn format_stats(stats: &sm::ChartStats) -> String {
+    let mut ret = format!(
+        "{} steps, {} jumps, {} hands, {} holds, {} rolls, {} mines in {} measures",
+        stats.steps,
+        stats.jumps,
+        stats.hands,
+        stats.holds,
+        stats.rolls,
+        stats.mines,
+        stats.measures
+    );
+    if let Some(length) = stats.length
+        && length > 0.0
+    {
+        let secs = length.round() as i32;
+        ret.push_str(&format!(
+            ", {}:{:02} long ({:.1} steps/s)",
+            secs / 60,
+            secs % 60,
+            stats.steps as f32 / length
+        ));
+    }
+    ret
+}
+
+fn analyze(opts: &InputOpts, p: &Path, is_ssc: bool) {
+    println!("analyzing {:?}", p);
+    let Some((contents, _)) = read_simfile(opts, p) else {
+        return;
+    };
+    let parsed = sm::parse_charts(&contents, is_ssc)
+        .and_then(|charts| Ok((charts, sm::song_timing(&contents, is_ssc)?)));
+    let (charts, timing) = match parsed {
+        Ok(p) => p,
+        Err(e) => {
+            println!("  skipped: {}", format_error(p, &e));
+            return;
+        }
+    };
+    for c in &charts {
+        match sm::chart_stats(c, &timing) {
+            Ok(stats) => println!("  {}: {}", c.summary(), format_stats(&stats)),
+            Err(e) => println!("  {}: {}", c.summary(), format_error(p, &e)),
+        }
+    }
+}
+
+// Prints everything that would stop itggen from generating from the file at p, and returns how
+// many problems there are.
+fn validate(opts: &InputOpts, p: &Path, is_ssc: bool) -> usize {
+    println!("validating {:?}", p);
+    let mut errors = Vec::new();
+    match encoding::read(p, opts.encoding) {
+        Ok((contents, _)) => {
+            match (
+                sm::parse_charts(&contents, is_ssc),
+                sm::song_timing(&contents, is_ssc),
+            ) {
+                (Ok(charts), Ok(timing)) => errors.extend(
+                    charts
+                        .iter()
+                        .filter_map(|c| sm::chart_stats(c, &timing).err()),
+                ),
+                (charts, timing) => errors.extend(charts.err().into_iter().chain(timing.err())),
+            }
+        }
+        Err(e) => errors.push(e),
+    }
+    errors.extend(metadata::load(p).err());
+    errors.extend(sidecar::Sidecar::for_song(p).err());
+    for e in &errors {
+        println!("  {}", format_error(p, e));
+    }
+    if errors.is_empty() {
+        println!("  ok");
+    }
+    errors.len()
+}
+
+fn main() -> Result<(), Error> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    for path in layout_paths(&args) {
+        layout::load(&path)?;
+    }
+
+    match Opt::from_iter(args).command {
+        Command::Generate(opts) => {
+            let choices = choices_from_opts(&opts)?;
+            let write = &opts.write;
+            mirror_inputs(write)?;
+            for (p, out, is_ssc) in input_files(
+                &write.input.inputs,
+                write.output_dir.as_deref(),
+                write.sync_sm,
+            ) {
+                generate(&opts, &choices, &p, &out, is_ssc)?;
+            }
+        }
+        Command::Regenerate(opts) => {
+            mirror_inputs(&opts)?;
+            for (p, out, is_ssc) in
+                input_files(&opts.input.inputs, opts.output_dir.as_deref(), opts.sync_sm)
+            {
+                regenerate(&opts, &p, &out, is_ssc)?;
+            }
+        }
+        Command::Strip(opts) => {
+            mirror_inputs(&opts)?;
+            for (p, out, is_ssc) in
+                input_files(&opts.input.inputs, opts.output_dir.as_deref(), opts.sync_sm)
+            {
+                strip(&opts, &p, &out, is_ssc)?;
+            }
+        }
+        Command::List(opts) => {
+            for (p, _, is_ssc) in input_files(&opts.inputs, None, false) {
+                list(&opts, &p, is_ssc);
+            }
+        }
+        Command::Analyze(opts) => {
+            for (p, _, is_ssc) in input_files(&opts.inputs, None, false) {
+                analyze(&opts, &p, is_ssc);
+            }
+        }
+        Command::Validate(opts) => {
+            let mut errors = 0;
+            for (p, _, is_ssc) in input_files(&opts.inputs, None, false) {
+                errors += validate(&opts, &p, is_ssc);
+            }
+            if errors > 0 {
+                println!("{} errors", errors);
+                std::process::exit(1);
+            }
+        }
+        Command::Restore(opts) => {
+            for (p, _, _) in input_files(&opts.input.inputs, None, true) {
+                println!("restoring {:?}", p);
+                match backup::restore(&p, opts.backup_dir.as_deref(), opts.dry_run) {
+                    Ok(Some(b)) if opts.dry_run => println!("  done (dry run, from {:?})", b),
+                    Ok(Some(b)) => println!("  done (from {:?})", b),
+                    Ok(None) => println!("  no backup"),
+                    Err(e) => println!("  skipped: {}", e),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_format_error() {
+    let path = Path::new("a/b.sm");
+    let e = itggen::ParseError::new(itggen::ParseErrorKind::UnknownNote('x'), 12);
+    assert_eq!(
+        format_error(path, &Error::Parse(e.clone())),
+        "a/b.sm:12: error: unknown note 'x'"
+    );
+    assert_eq!(
+        format_error(path, &Error::Parse(e.in_chart(1))),
+        "a/b.sm:12: error: unknown note 'x' (chart 2)"
+    );
+    assert_eq!(
+        format_error(
+            path,
+            &Error::Generate("no available columns for row 3".to_owned())
+        ),
+        "a/b.sm: error: no available columns for row 3"
+    );
+}
+
+#[test]
+fn test_builtin_presets() {
+    let opts = |args: &[&str]| {
+        GenerateOpts::from_iter([&["generate", "-i", "itg-singles"], args].concat())
+    };
+    let params_json =
+        |args: &[&str]| serde_json::to_value(params_from_opts(&opts(args)).unwrap().0).unwrap();
+    assert_eq!(params_json(&["--preset", "default"]), params_json(&[]));
+    assert_eq!(
+        params_json(&["--preset", "crossovers"]),
+        params_json(&["-c"])
+    );
+    assert_eq!(
+        params_json(&["--preset", "hard-crossovers"]),
+        params_json(&["-cc"])
+    );
+    assert_eq!(
+        params_json(&["--preset", "easy-crossovers"]),
+        params_json(&["-c", "--more-easy-crossovers"])
+    );
+    assert_eq!(
+        params_json(&["--preset", "vroom"]),
+        params_json(&["--vroom"])
+    );
+    assert_eq!(
+        params_json(&["--preset", "crossovers", "-cc", "--vroom"]),
+        params_json(&["-cc", "--vroom"])
+    );
+    assert_eq!(
+        params_json(&["--preset", "hard-crossovers", "--more-easy-crossovers"]),
+        params_json(&["-cc", "--more-easy-crossovers"])
+    );
+    assert!(params_from_opts(&opts(&["--preset", "/nonexistent.toml"])).is_err());
+}
+
+#[test]
+fn test_preset_file_flags() {
+    let path = std::env::temp_dir().join(format!("itggen-flags-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "seed = 5\nmax_angle = 1.0\ndisallow_footswitch = true\nhands = \"brackets\"\nrepeated_decay = [2, 0.5]\nbeam_width = 2\n",
+    )
+    .unwrap();
+    let params = |args: &[&str]| {
+        let opts = GenerateOpts::from_iter(
+            [
+                &[
+                    "generate",
+                    "-i",
+                    "itg-singles",
+                    "--preset",
+                    path.to_str().unwrap(),
+                ],
+                args,
+            ]
+            .concat(),
+        );
+        params_from_opts(&opts).unwrap().0
+    };
+    let p = params(&[]);
+    assert_eq!(p.seed, Some(5));
+    assert_eq!(p.max_angle, Some(1.0));
+    assert!(p.disallow_footswitch);
+    assert_eq!(p.hands, HandsPolicy::Brackets);
+    assert_eq!(p.repeated_decay, Some((2, 0.5)));
+    assert_eq!(p.beam_width, Some(2));
+    // every flag that is given wins over the file, the rest of it still applies
+    let p = params(&["-c", "-f", "-p", "--hands", "jumps", "--beam", "3"]);
+    assert_eq!(p.seed, Some(5));
+    assert_eq!(p.max_angle, Some(std::f32::consts::PI * 0.8));
+    assert!(!p.disallow_footswitch);
+    assert_eq!(p.hands, HandsPolicy::Jumps);
+    assert_eq!(p.repeated_decay, None);
+    assert_eq!(p.beam_width, Some(3));
+    assert_eq!(params(&["--seed", "7"]).seed, Some(7));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_commands() {
+    let command = |args: &[&str]| Command::from_iter_safe([&["itggen"], args].concat());
+    assert!(matches!(
+        command(&["strip", "-d", "--diff", "a"]),
+        Ok(Command::Strip(WriteOpts {
+            dry_run: true,
+            diff: true,
+            ..
+        }))
+    ));
+    assert!(matches!(
+        command(&[
+            "generate",
+            "-i",
+            "itg-singles",
+            "-o",
+            "itg-doubles",
+            "-r",
+            "a"
+        ]),
+        Ok(Command::Generate(GenerateOpts {
+            remove_existing_autogen: true,
+            ..
+        }))
+    ));
+    assert!(command(&["generate", "a"]).is_err());
+    assert!(command(&["list", "--hardlink", "a"]).is_err());
+    assert!(command(&["strip", "--hardlink", "a"]).is_err());
+    assert!(command(&["a"]).is_err());
+
+    let args: Vec<OsString> = [
+        "itggen",
+        "generate",
+        "--layout",
+        "a.toml",
+        "--layout=b.json",
+        "-i",
+        "itg-singles",
+        "--",
+        "--layout",
+    ]
+    .iter()
+    .map(OsString::from)
+    .collect();
+    assert_eq!(
+        layout_paths(&args),
+        vec![PathBuf::from("a.toml"), PathBuf::from("b.json")]
+    );
+    assert!(Opt::from_iter_safe(&args[..7]).is_ok());
+    assert!(Opt::from_iter_safe(["itggen", "--layout", "a.toml", "list", "a"]).is_ok());
+}
+
+#[test]
+fn test_from_styles() {
+    let charts = sm::parse_charts(
+        "#NOTES:pump-single:a:Hard:9::10000;\n#NOTES:dance-single:AYEAG - a:Hard:9::1000;\n#NOTES:dance-double:b:Hard:9::10000000;\n#NOTES:kb7-single:c:Hard:9::1000000;\n#NOTES:pump-single:d:Easy:3::10000;",
+        false,
+    )
+    .unwrap();
+    let parse = |s: &str| -> Vec<FromStyle> { s.split(',').map(|s| s.parse().unwrap()).collect() };
+    assert_eq!(
+        from_styles(&parse("auto"), &charts),
+        vec![Style::PumpSingles, Style::ItgDoubles]
+    );
+    assert_eq!(
+        from_styles(&parse("itg-doubles,auto,itg-singles"), &charts),
+        vec![Style::ItgDoubles, Style::PumpSingles, Style::ItgSingles]
+    );
+    assert!("dance-single".parse::<FromStyle>().is_err());
 }
@@ -0,0 +1,102 @@
+use crate::backup;
+use crate::error::Error;
+use crate::generator::GeneratorParameters;
+use crate::style::Style;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// Everything needed to generate a chart again exactly as it was, stored for every generated
+// chart in a sidecar JSON file next to the .sm/.ssc.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChartMetadata {
+    // itggen version that generated the chart
+    pub version: String,
+    pub from_style: Style,
+    // the chart generated from
+    pub from_difficulty: String,
+    pub from_description: String,
+    pub from_level: i32,
+    pub to_style: Style,
+    // the difficulty written instead of from_difficulty, for charts generated from a chart of
+    // another difficulty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_difficulty: Option<String>,
+    pub edit: bool,
+    pub extra_description: Option<String>,
+    pub write_from_difficulty: bool,
+    // with the seed used and any curves already applied
+    pub params: GeneratorParameters,
+}
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// e.g. "song.sm" -> "song.sm.itggen.json"
+pub fn path_for(song_path: &Path) -> PathBuf {
+    let mut s = song_path.as_os_str().to_owned();
+    s.push(".itggen.json");
+    PathBuf::from(s)
+}
+
+// Returns the metadata of the charts generated for song_path, or nothing if there is none.
+pub fn load(song_path: &Path) -> Result<Vec<ChartMetadata>, Error> {
+    let path = path_for(song_path);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| Error::Config(format!("Couldn't read {:?}: {}", path, e)))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| Error::Config(format!("Couldn't parse {:?}: {}", path, e)))
+}
+
+// Writes the metadata for song_path, removing the file if there is nothing to write.
+pub fn save(song_path: &Path, metadata: &[ChartMetadata]) -> std::io::Result<()> {
+    let path = path_for(song_path);
+    if metadata.is_empty() {
+        if path.is_file() {
+            std::fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    let mut contents = serde_json::to_string_pretty(metadata).map_err(std::io::Error::other)?;
+    contents.push('\n');
+    backup::write_atomic(&path, contents.as_bytes())
+}
+
+#[test]
+fn test_save_load() {
+    let dir = std::env::temp_dir().join(format!("itggen-metadata-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let song_path = dir.join("song.sm");
+    assert_eq!(path_for(&song_path), dir.join("song.sm.itggen.json"));
+    assert!(load(&song_path).unwrap().is_empty());
+    let m = ChartMetadata {
+        version: VERSION.to_owned(),
+        from_style: Style::ItgSingles,
+        from_difficulty: "Hard".to_owned(),
+        from_description: "A".to_owned(),
+        from_level: 9,
+        to_style: Style::PumpDoublesBrackets,
+        to_difficulty: None,
+        edit: true,
+        extra_description: None,
+        write_from_difficulty: true,
+        params: GeneratorParameters {
+            seed: Some(u64::MAX),
+            max_angle: Some(std::f32::consts::PI * 0.8),
+            ..GeneratorParameters::default()
+        },
+    };
+    save(&song_path, std::slice::from_ref(&m)).unwrap();
+    let loaded = load(&song_path).unwrap();
+    assert_eq!(loaded.len(), 1);
+    assert_eq!(loaded[0].to_style, Style::PumpDoublesBrackets);
+    assert_eq!(loaded[0].params.seed, Some(u64::MAX));
+    assert_eq!(loaded[0].params.max_angle, m.params.max_angle);
+    save(&song_path, &[]).unwrap();
+    assert!(!path_for(&song_path).exists());
+    std::fs::write(path_for(&song_path), "[{}]").unwrap();
+    assert!(load(&song_path).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
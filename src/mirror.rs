@@ -0,0 +1,101 @@
+// Writing generated charts into a copy of a pack instead of the pack itself.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Returns where path, found under the input root, goes in output_dir.
+pub fn output_path(root: &Path, path: &Path, output_dir: &Path) -> PathBuf {
+    match path.strip_prefix(root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => output_dir.join(relative),
+        _ => output_dir.join(path.file_name().unwrap_or_default()),
+    }
+}
+
+// Files itggen writes to, which must never be hardlinked to the originals.
+fn is_written(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    name.ends_with(".sm") || name.ends_with(".ssc") || name.ends_with(".itggen.json")
+}
+
+// Copies everything in src_dir into dst_dir, replacing what is already there. With hardlink,
+// audio, graphics etc. are hardlinked instead where the filesystem allows it. dst_dir can't be
+// in src_dir, or the copy would copy itself.
+pub fn copy_dir(src_dir: &Path, dst_dir: &Path, hardlink: bool) -> io::Result<()> {
+    std::fs::create_dir_all(dst_dir)?;
+    if std::fs::canonicalize(dst_dir)?.starts_with(std::fs::canonicalize(src_dir)?) {
+        return Err(io::Error::other(format!(
+            "{:?} is or contains the output {:?}",
+            src_dir, dst_dir
+        )));
+    }
+    for de in std::fs::read_dir(src_dir)? {
+        let de = de?;
+        let src = de.path();
+        let dst = dst_dir.join(de.file_name());
+        if de.file_type()?.is_dir() {
+            copy_dir(&src, &dst, hardlink)?;
+            continue;
+        }
+        if dst.exists() {
+            std::fs::remove_file(&dst)?;
+        }
+        if !hardlink || is_written(&src) || std::fs::hard_link(&src, &dst).is_err() {
+            std::fs::copy(&src, &dst)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_output_path() {
+    let out = Path::new("/out");
+    assert_eq!(
+        output_path(Path::new("/pack"), Path::new("/pack/song/a.sm"), out),
+        Path::new("/out/song/a.sm")
+    );
+    assert_eq!(
+        output_path(
+            Path::new("/pack/song/a.sm"),
+            Path::new("/pack/song/a.sm"),
+            out
+        ),
+        Path::new("/out/a.sm")
+    );
+}
+
+#[test]
+fn test_copy_dir() {
+    let dir = std::env::temp_dir().join(format!("itggen-mirror-{}", std::process::id()));
+    let src = dir.join("pack/song");
+    let dst = dir.join("pack +doubles/song");
+    std::fs::create_dir_all(src.join("lyrics")).unwrap();
+    std::fs::write(src.join("song.sm"), "#TITLE:a;").unwrap();
+    std::fs::write(src.join("song.ogg"), "ogg").unwrap();
+    std::fs::write(src.join("lyrics/song.lrc"), "lrc").unwrap();
+    copy_dir(&src, &dst, true).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dst.join("lyrics/song.lrc")).unwrap(),
+        "lrc"
+    );
+    // writing the copied simfile leaves the original alone
+    std::fs::write(dst.join("song.sm"), "#TITLE:b;").unwrap();
+    assert_eq!(
+        std::fs::read_to_string(src.join("song.sm")).unwrap(),
+        "#TITLE:a;"
+    );
+    copy_dir(&src, &dst, false).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dst.join("song.sm")).unwrap(),
+        "#TITLE:a;"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dst.join("song.ogg")).unwrap(),
+        "ogg"
+    );
+    assert!(copy_dir(&src, &src, false).is_err());
+    assert!(copy_dir(&dir.join("pack"), &src.join("out"), false).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
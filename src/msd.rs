@@ -0,0 +1,198 @@
+// Tokenizer for the MSD format .sm and .ssc files are written in, following StepMania's MsdFile.
+//
+// A file is a list of '#PARAM:PARAM:...;' values, where the first param is the key. Text outside
+// of values is ignored, '//' starts a comment that runs to the end of the line, '\' escapes the
+// next character, and a '#' that starts a line ends a value that is missing its ';'.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    // unescaped and without comments, but not trimmed
+    pub text: String,
+    // line number the param starts on
+    pub line: usize,
+}
+
+impl Param {
+    // Returns the lines of the param with their line numbers.
+    pub fn lines(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.text
+            .split('\n')
+            .enumerate()
+            .map(|(i, l)| (self.line + i, l))
+    }
+
+    // Returns the line number the trimmed text starts on.
+    pub fn text_line(&self) -> usize {
+        let leading = self.text.len() - self.text.trim_start().len();
+        self.line + self.text[..leading].matches('\n').count()
+    }
+
+    // Returns the line number the param ends on.
+    pub fn end_line(&self) -> usize {
+        self.line + self.text.matches('\n').count()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Value {
+    pub params: Vec<Param>,
+    // byte offset of the '#'
+    pub start: usize,
+    // byte offset just past the ';', or where the value was cut off
+    pub end: usize,
+    // the file ended before the value's ';'
+    pub truncated: bool,
+}
+
+impl Value {
+    // e.g. "NOTES"
+    pub fn key(&self) -> String {
+        self.params[0].text.trim().to_uppercase()
+    }
+
+    pub fn line(&self) -> usize {
+        self.params[0].line
+    }
+
+    // Returns param i trimmed, or "" if there aren't that many.
+    pub fn param(&self, i: usize) -> &str {
+        self.params.get(i).map_or("", |p| p.text.trim())
+    }
+}
+
+// Returns true if everything before idx on its line is whitespace.
+fn starts_line(contents: &str, idx: usize) -> bool {
+    contents[..idx]
+        .chars()
+        .rev()
+        .take_while(|&c| c != '\n')
+        .all(char::is_whitespace)
+}
+
+pub fn parse(contents: &str) -> Vec<Value> {
+    let mut ret: Vec<Value> = Vec::new();
+    let mut reading_value = false;
+    let mut line = 1;
+    let mut chars = contents.char_indices().peekable();
+    // like StepMania, ignore a UTF-8 byte order mark
+    if contents.starts_with('\u{feff}') {
+        chars.next();
+    }
+    while let Some((i, c)) = chars.next() {
+        if c == '/' && chars.peek().map(|&(_, c)| c) == Some('/') {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            continue;
+        }
+        if c == '#' && reading_value && starts_line(contents, i) {
+            // a missing ';', the value ends where the last param's text does
+            let value = ret.last_mut().unwrap();
+            value.end = i;
+            value.truncated = false;
+            let param = value.params.last_mut().unwrap();
+            let trimmed_len = param.text.trim_end().len();
+            param.text.truncate(trimmed_len);
+            reading_value = false;
+        }
+        if !reading_value {
+            if c == '#' {
+                ret.push(Value {
+                    params: vec![Param {
+                        text: String::new(),
+                        line,
+                    }],
+                    start: i,
+                    end: contents.len(),
+                    truncated: true,
+                });
+                reading_value = true;
+            } else if c == '\\' {
+                chars.next_if(|&(_, c)| c != '\n');
+            } else if c == '\n' {
+                line += 1;
+            }
+            continue;
+        }
+        let value = ret.last_mut().unwrap();
+        match c {
+            ':' => value.params.push(Param {
+                text: String::new(),
+                line,
+            }),
+            ';' => {
+                value.end = i + 1;
+                value.truncated = false;
+                reading_value = false;
+            }
+            '\\' => {
+                if let Some((_, c)) = chars.next() {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    value.params.last_mut().unwrap().text.push(c);
+                }
+            }
+            _ => {
+                if c == '\n' {
+                    line += 1;
+                }
+                value.params.last_mut().unwrap().text.push(c);
+            }
+        }
+    }
+    ret
+}
+
+// Escapes s so that parse() reads it back as one param.
+pub fn escape(s: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if matches!(c, ':' | ';' | '\\' | '#') || (c == '/' && chars.peek() == Some(&'/')) {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+#[test]
+fn test_parse() {
+    let contents = "\u{feff}junk\r\n#TITLE:a\\:b // c\r\n;\n#NOTES:x:y\n#BPMS:0=1\n;x";
+    let values = parse(contents);
+    assert_eq!(values.len(), 3);
+    assert_eq!(values[0].key(), "TITLE");
+    assert_eq!(values[0].params[1].text, "a:b \n");
+    assert_eq!(values[0].param(1), "a:b");
+    assert_eq!(values[0].line(), 2);
+    assert_eq!(values[1].key(), "NOTES");
+    assert_eq!(values[1].params[2].text, "y");
+    assert_eq!(values[1].param(3), "");
+    assert_eq!(values[2].line(), 5);
+    assert_eq!(values[2].params[1].end_line(), 6);
+    assert_eq!(values[0].params[1].text_line(), 2);
+    assert_eq!(parse("#A:\n\n b;")[0].params[1].text_line(), 3);
+    assert_eq!(
+        &values[2].params[1].lines().collect::<Vec<_>>(),
+        &[(5, "0=1"), (6, "")]
+    );
+    assert_eq!(&contents[values[1].start..values[1].end], "#NOTES:x:y\n");
+    assert_eq!(&contents[values[2].start..values[2].end], "#BPMS:0=1\n;");
+
+    // a '#' in the middle of a line doesn't start a value
+    let values = parse("#TITLE:a#b;");
+    assert_eq!(values.len(), 1);
+    assert_eq!(values[0].param(1), "a#b");
+
+    assert!(parse("// #TITLE:a;\n").is_empty());
+    assert!(parse("#TITLE:a")[0].truncated);
+    assert!(!values[0].truncated);
+}
+
+#[test]
+fn test_escape() {
+    let s = "a:b;c\\d#e/f//g";
+    let values = parse(&format!("#TITLE:{};", escape(s)));
+    assert_eq!(values[0].params.len(), 2);
+    assert_eq!(values[0].param(1), s);
+    assert_eq!(escape("a/b"), "a/b");
+}
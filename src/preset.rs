@@ -0,0 +1,456 @@
+use crate::error::Error;
+use crate::generator::{GeneratorParameters, HandsPolicy};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::path::Path;
+
+// A named combination of the flags that pick generator parameters.
+pub struct BuiltinPreset {
+    pub name: &'static str,
+    pub crossovers: i32,
+    pub more_easy_crossovers: bool,
+    pub vroom: bool,
+}
+
+pub const BUILTIN_PRESETS: [BuiltinPreset; 5] = [
+    BuiltinPreset {
+        name: "default",
+        crossovers: 0,
+        more_easy_crossovers: false,
+        vroom: false,
+    },
+    BuiltinPreset {
+        name: "crossovers",
+        crossovers: 1,
+        more_easy_crossovers: false,
+        vroom: false,
+    },
+    BuiltinPreset {
+        name: "hard-crossovers",
+        crossovers: 2,
+        more_easy_crossovers: false,
+        vroom: false,
+    },
+    BuiltinPreset {
+        name: "easy-crossovers",
+        crossovers: 1,
+        more_easy_crossovers: true,
+        vroom: false,
+    },
+    BuiltinPreset {
+        name: "vroom",
+        crossovers: 0,
+        more_easy_crossovers: false,
+        vroom: true,
+    },
+];
+
+pub fn builtin(name: &str) -> Option<&'static BuiltinPreset> {
+    BUILTIN_PRESETS.iter().find(|p| p.name == name)
+}
+
+// The parameters picked by itggen's command line flags.
+#[allow(clippy::too_many_arguments)]
+pub fn create_params(
+    seed: Option<u64>,
+    crossovers: i32,
+    more_easy_crossovers: bool,
+    vroom: bool,
+    preserve_input_repetitions: bool,
+    disallow_footswitch: bool,
+    hands: HandsPolicy,
+    min_difficulty: Option<i32>,
+    max_difficulty: Option<i32>,
+) -> GeneratorParameters {
+    let has_crossovers = crossovers != 0;
+    GeneratorParameters {
+        seed,
+        disallow_footswitch,
+        max_repeated: None,
+        repeated_decay: if preserve_input_repetitions {
+            None
+        } else {
+            Some((1, 0.1))
+        },
+        other_foot_repeat_decay: if preserve_input_repetitions {
+            None
+        } else {
+            Some(0.3)
+        },
+        max_dist_between_feet: Some(2.9),
+        max_dist_between_feet_if_crossover: Some(2.5),
+        dist_between_feet_decay: None,
+        max_dist_between_steps: Some(if has_crossovers || vroom { 2.9 } else { 2.1 }),
+        dist_between_steps_decay: Some((2.5, 0.3)),
+        max_horizontal_dist_between_steps: if has_crossovers || vroom {
+            None
+        } else {
+            Some(1.0)
+        },
+        horizontal_dist_between_steps_decay: None,
+        max_horizontal_dist_between_steps_if_crossover: if more_easy_crossovers {
+            Some(1.9)
+        } else {
+            None
+        },
+        max_vertical_dist_between_steps: None,
+        vertical_dist_between_steps_decay: None,
+        timed_max_dist_between_steps: None,
+        timed_max_horizontal_dist_between_steps: None,
+        horizontal_dist_between_3_steps_same_foot_decay: None,
+        max_horizontal_dist_between_4_steps_both_feet: if has_crossovers || vroom {
+            None
+        } else {
+            Some(2.5)
+        },
+        max_horizontal_dist_between_3_steps_same_foot: if has_crossovers || vroom {
+            None
+        } else {
+            Some(1.5)
+        },
+        max_angle: Some(PI * (0.5 + 0.3 * (crossovers as f32))),
+        angle_decay: None,
+        max_turn: Some(if crossovers > 1 { PI } else { PI * 3.0 / 4.0 }),
+        turn_decay: None,
+        crossover_multiplier: if more_easy_crossovers {
+            Some(2.0)
+        } else {
+            None
+        },
+        max_bar_angle: None,
+        bar_angle_decay: Some((0.0, if has_crossovers || vroom { 0.4 } else { 0.9 })),
+        preserve_input_repetitions: if preserve_input_repetitions {
+            Some(0.01)
+        } else {
+            None
+        },
+        doubles_movement: Some((0.5, 0.002)),
+        doubles_dist_from_side: if vroom { Some(0.0) } else { None },
+        doubles_steps_per_dist: if vroom { Some(2.5) } else { None },
+        doubles_track_individual_feet: !vroom && !has_crossovers,
+        beam_width: None,
+        disallow_foot_opposite_side: !has_crossovers,
+        remove_jumps: has_crossovers,
+        hands,
+        min_difficulty,
+        max_difficulty,
+    }
+}
+
+// The GeneratorParameters fields set by a preset, by field name.
+pub type Overrides = Map<String, Value>;
+
+// How a GeneratorParameters field changes with the level of the chart being generated from,
+// as (level, value) points sorted by level.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum Curve {
+    // interpolated between the points around the level, and clamped to the first/last point
+    // outside them. Non-numeric values switch at the next point.
+    Linear(Vec<(i32, Value)>),
+    // the value of the last point at or below the level, unchanged below the first point
+    Steps(Vec<(i32, Value)>),
+}
+
+fn lerp(a: &Value, b: &Value, t: f64) -> Value {
+    match (a, b) {
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            Value::Array(a.iter().zip(b).map(|(a, b)| lerp(a, b, t)).collect())
+        }
+        (Value::Number(na), Value::Number(nb)) => {
+            let v = na.as_f64().unwrap() + (nb.as_f64().unwrap() - na.as_f64().unwrap()) * t;
+            if na.is_f64() || nb.is_f64() {
+                Value::from(v)
+            } else {
+                Value::from(v.round() as i64)
+            }
+        }
+        _ if t < 1.0 => a.clone(),
+        _ => b.clone(),
+    }
+}
+
+impl Curve {
+    pub fn value_at(&self, level: i32) -> Option<Value> {
+        match self {
+            Curve::Linear(points) => {
+                let (first, last) = (points.first()?, points.last()?);
+                if level <= first.0 {
+                    return Some(first.1.clone());
+                }
+                if level >= last.0 {
+                    return Some(last.1.clone());
+                }
+                let w = points.windows(2).find(|w| level < w[1].0)?;
+                let t = (level - w[0].0) as f64 / (w[1].0 - w[0].0) as f64;
+                Some(lerp(&w[0].1, &w[1].1, t))
+            }
+            Curve::Steps(points) => points
+                .iter()
+                .take_while(|(l, _)| *l <= level)
+                .last()
+                .map(|(_, v)| v.clone()),
+        }
+    }
+}
+
+// Curves by GeneratorParameters field name.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct ParamCurves(BTreeMap<String, Curve>);
+
+impl ParamCurves {
+    // Adds the curves in other, replacing any for the same field.
+    pub fn extend(&mut self, other: ParamCurves) {
+        self.0.extend(other.0);
+    }
+
+    // Removes the curve for field, if there is one.
+    pub fn remove(&mut self, field: &str) {
+        self.0.remove(field);
+    }
+
+    // Returns params for generating from a chart of the given level.
+    pub fn resolve(
+        &self,
+        params: GeneratorParameters,
+        level: i32,
+    ) -> Result<GeneratorParameters, Error> {
+        let mut overrides = Overrides::new();
+        for (field, curve) in &self.0 {
+            if let Some(v) = curve.value_at(level) {
+                overrides.insert(field.clone(), v);
+            }
+        }
+        apply(params, &overrides)
+    }
+}
+
+// Parses a preset as JSON if is_json, otherwise as TOML. Parameter curves are under the
+// "curves" key.
+pub fn parse(contents: &str, is_json: bool) -> Result<(Overrides, ParamCurves), Error> {
+    let mut overrides: Overrides = if is_json {
+        serde_json::from_str(contents).map_err(|e| Error::Config(e.to_string()))?
+    } else {
+        toml::from_str(contents).map_err(|e| Error::Config(e.to_string()))?
+    };
+    let curves = match overrides.remove("curves") {
+        Some(c) => serde_json::from_value(c)
+            .map_err(|e| Error::Config(format!("Invalid curves: {}", e)))?,
+        None => ParamCurves::default(),
+    };
+    Ok((overrides, curves))
+}
+
+pub fn load(path: &Path) -> Result<(Overrides, ParamCurves), Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("Couldn't read preset {:?}: {}", path, e)))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|e| e.to_string_lossy().to_lowercase() == "json");
+    parse(&contents, is_json)
+        .map_err(|e| Error::Config(format!("Couldn't parse preset {:?}: {}", path, e)))
+}
+
+// Whether v, the value a preset sets field to, unsets it. TOML has no null, so false and "none"
+// also unset the fields that aren't bools or strings.
+fn unsets(field: &Value, v: &Value) -> bool {
+    match v {
+        Value::Null => true,
+        Value::Bool(false) => !field.is_boolean(),
+        Value::String(s) if s == "none" => !field.is_string(),
+        _ => false,
+    }
+}
+
+// Returns the fields of params that are different in base.
+pub fn diff(params: GeneratorParameters, base: GeneratorParameters) -> Result<Overrides, Error> {
+    let to_value = |p| serde_json::to_value(p).map_err(|e| Error::Config(e.to_string()));
+    let (Value::Object(fields), Value::Object(base)) = (to_value(params)?, to_value(base)?) else {
+        unreachable!("GeneratorParameters is a struct");
+    };
+    Ok(fields
+        .into_iter()
+        .filter(|(k, v)| base.get(k) != Some(v))
+        .collect())
+}
+
+// Returns params with every field in overrides replaced.
+pub fn apply(
+    params: GeneratorParameters,
+    overrides: &Overrides,
+) -> Result<GeneratorParameters, Error> {
+    let mut value = serde_json::to_value(params).map_err(|e| Error::Config(e.to_string()))?;
+    let fields = value.as_object_mut().unwrap();
+    for (k, v) in overrides {
+        let v = match fields.get(k) {
+            Some(field) if unsets(field, v) => Value::Null,
+            _ => v.clone(),
+        };
+        fields.insert(k.clone(), v);
+    }
+    serde_json::from_value(value).map_err(|e| Error::Config(format!("Invalid preset: {}", e)))
+}
+
+#[test]
+fn test_apply() {
+    use crate::generator::HandsPolicy;
+    let params = GeneratorParameters {
+        max_turn: Some(1.0),
+        max_angle: Some(2.0),
+        ..GeneratorParameters::default()
+    };
+    let (overrides, _) = parse(
+        "max_turn = 3.0\nrepeated_decay = [2, 0.5]\ndisallow_footswitch = true\nhands = \"brackets\"\n",
+        false,
+    )
+    .unwrap();
+    let p = apply(params, &overrides).unwrap();
+    assert_eq!(p.max_turn, Some(3.0));
+    assert_eq!(p.max_angle, Some(2.0));
+    assert_eq!(p.repeated_decay, Some((2, 0.5)));
+    assert!(p.disallow_footswitch);
+    assert_eq!(p.hands, HandsPolicy::Brackets);
+
+    let (overrides, _) = parse("{\"max_angle\": null, \"seed\": 5}", true).unwrap();
+    let p = apply(params, &overrides).unwrap();
+    assert_eq!(p.max_angle, None);
+    assert_eq!(p.max_turn, Some(1.0));
+    assert_eq!(p.seed, Some(5));
+
+    // TOML can't write null
+    let (overrides, _) = parse(
+        "max_angle = false\nmax_turn = \"none\"\ndisallow_footswitch = false\n",
+        false,
+    )
+    .unwrap();
+    let p = apply(
+        GeneratorParameters {
+            disallow_footswitch: true,
+            ..params
+        },
+        &overrides,
+    )
+    .unwrap();
+    assert_eq!(p.max_angle, None);
+    assert_eq!(p.max_turn, None);
+    assert!(!p.disallow_footswitch);
+    assert!(apply(params, &parse("hands = \"none\"", false).unwrap().0).is_err());
+    assert!(apply(params, &parse("remove_jumps = \"none\"", false).unwrap().0).is_err());
+
+    assert!(apply(params, &parse("max_trun = 3.0", false).unwrap().0).is_err());
+    assert!(apply(params, &parse("max_turn = \"a\"", false).unwrap().0).is_err());
+    assert!(parse("max_turn = ", false).is_err());
+}
+
+#[test]
+fn test_curves() {
+    let (overrides, curves) = parse(
+        "max_turn = 1.0\n[curves]\ndoubles_steps_per_dist = { linear = [[5, 1.0], [15, 3.0]] }\ndoubles_movement = { linear = [[5, [0.5, 0.0]], [15, [1.5, 0.1]]] }\nmax_repeated = { linear = [[1, 1], [3, 2]] }\ndisallow_foot_opposite_side = { steps = [[1, true], [11, false]] }\nmax_angle = { steps = [[11, 2.5]] }\n",
+        false,
+    )
+    .unwrap();
+    assert_eq!(overrides.len(), 1);
+    let params = GeneratorParameters {
+        max_angle: Some(1.5),
+        ..GeneratorParameters::default()
+    };
+
+    let p = curves.resolve(params, 1).unwrap();
+    assert_eq!(p.doubles_steps_per_dist, Some(1.0));
+    assert_eq!(p.doubles_movement, Some((0.5, 0.0)));
+    assert_eq!(p.max_repeated, Some(1));
+    assert!(p.disallow_foot_opposite_side);
+    assert_eq!(p.max_angle, Some(1.5));
+
+    let p = curves.resolve(params, 10).unwrap();
+    assert_eq!(p.doubles_steps_per_dist, Some(2.0));
+    assert_eq!(p.doubles_movement, Some((1.0, 0.05)));
+    assert_eq!(p.max_repeated, Some(2));
+    assert!(p.disallow_foot_opposite_side);
+
+    let p = curves.resolve(params, 11).unwrap();
+    assert!(!p.disallow_foot_opposite_side);
+    assert_eq!(p.max_angle, Some(2.5));
+    let p = curves.resolve(params, 20).unwrap();
+    assert_eq!(p.doubles_steps_per_dist, Some(3.0));
+
+    let (_, bad) = parse("[curves]\nmax_trun = { steps = [[1, 1.0]] }\n", false).unwrap();
+    assert!(bad.resolve(params, 1).is_err());
+    assert!(parse("[curves]\nmax_turn = { cubic = [[1, 1.0]] }\n", false).is_err());
+}
+
+#[test]
+fn test_params() {
+    use crate::style::Style;
+    use rand::prelude::*;
+    let mut chart =
+        "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n"
+            .to_owned();
+    let mut rng = StdRng::from_os_rng();
+    for _ in 0..500 {
+        chart.push_str(match rng.random_range(0..4) {
+            0 => "1000",
+            1 => "0100",
+            2 => "0010",
+            3 => "0001",
+            _ => panic!(),
+        });
+        chart.push('\n');
+    }
+    chart.push(';');
+    for to_style in [Style::ItgDoubles, Style::PumpSingles, Style::PumpDoubles] {
+        let check_params = |params: GeneratorParameters| {
+            let g = crate::sm::generate(
+                &chart,
+                Style::ItgSingles,
+                to_style,
+                params,
+                &ParamCurves::default(),
+                crate::sm::DifficultyPolicy::Same,
+                None,
+                false,
+            );
+            assert!(g.is_ok());
+        };
+        for crossovers in 0..=2 {
+            for preserve in [false, true] {
+                check_params(create_params(
+                    None,
+                    crossovers,
+                    false,
+                    false,
+                    preserve,
+                    true,
+                    HandsPolicy::Jumps,
+                    None,
+                    None,
+                ));
+            }
+        }
+        check_params(create_params(
+            None,
+            1,
+            true,
+            false,
+            false,
+            true,
+            HandsPolicy::Jumps,
+            None,
+            None,
+        ));
+        check_params(create_params(
+            None,
+            0,
+            false,
+            true,
+            false,
+            true,
+            HandsPolicy::Jumps,
+            None,
+            None,
+        ));
+    }
+}
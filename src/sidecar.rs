@@ -0,0 +1,203 @@
+use crate::error::Error;
+use crate::generator::GeneratorParameters;
+use crate::preset;
+use crate::style::Style;
+use serde::Deserialize;
+use std::path::Path;
+
+pub const SIDECAR_FILE_NAME: &str = "itggen.toml";
+
+// Settings from an itggen.toml next to a song or in its pack folder, overriding the command
+// line for the songs it applies to.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sidecar {
+    // e.g. ["itg-doubles", "pump-doubles"]
+    pub to_style: Option<Vec<String>>,
+    // like -c (the number of times it's given), --more-easy-crossovers and --vroom, picking the
+    // parameters that params then changes
+    pub crossovers: Option<i32>,
+    pub more_easy_crossovers: Option<bool>,
+    pub vroom: Option<bool>,
+    pub edits: Option<bool>,
+    pub min_difficulty: Option<i32>,
+    pub max_difficulty: Option<i32>,
+    // GeneratorParameters fields, like a preset
+    pub params: preset::Overrides,
+    // GeneratorParameters fields that depend on the chart's level
+    pub curves: preset::ParamCurves,
+}
+
+impl Sidecar {
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        toml::from_str(contents).map_err(|e| Error::Config(e.to_string()))
+    }
+
+    // Returns the sidecar in dir, or an empty one if there is none.
+    fn load_dir(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(SIDECAR_FILE_NAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| Error::Config(format!("Couldn't read {:?}: {}", path, e)))?;
+        Self::parse(&contents)
+            .map_err(|e| Error::Config(format!("Couldn't parse {:?}: {}", path, e)))
+    }
+
+    // Returns the settings for the song at song_path, from its pack's sidecar overridden by
+    // its own.
+    pub fn for_song(song_path: &Path) -> Result<Self, Error> {
+        let Some(song_dir) = song_path.parent() else {
+            return Ok(Self::default());
+        };
+        let pack = match song_dir.parent() {
+            Some(pack_dir) => Self::load_dir(pack_dir)?,
+            None => Self::default(),
+        };
+        Ok(pack.merge(Self::load_dir(song_dir)?))
+    }
+
+    // Returns self with everything set in other replaced.
+    fn merge(mut self, other: Self) -> Self {
+        self.to_style = other.to_style.or(self.to_style);
+        self.crossovers = other.crossovers.or(self.crossovers);
+        self.more_easy_crossovers = other.more_easy_crossovers.or(self.more_easy_crossovers);
+        self.vroom = other.vroom.or(self.vroom);
+        self.edits = other.edits.or(self.edits);
+        self.min_difficulty = other.min_difficulty.or(self.min_difficulty);
+        self.max_difficulty = other.max_difficulty.or(self.max_difficulty);
+        // the song's params win over the pack's curves too
+        for field in other.params.keys() {
+            self.curves.remove(field);
+        }
+        self.params.extend(other.params);
+        self.curves.extend(other.curves);
+        self
+    }
+
+    pub fn to_styles(&self, default: &[Style]) -> Result<Vec<Style>, Error> {
+        match &self.to_style {
+            Some(styles) => styles
+                .iter()
+                .map(|s| s.parse::<Style>().map_err(|e| Error::Config(e.to_string())))
+                .collect(),
+            None => Ok(default.to_vec()),
+        }
+    }
+
+    pub fn edits(&self, default: bool) -> bool {
+        self.edits.unwrap_or(default)
+    }
+
+    pub fn apply(&self, params: GeneratorParameters) -> Result<GeneratorParameters, Error> {
+        let params = preset::apply(params, &self.params)?;
+        Ok(GeneratorParameters {
+            min_difficulty: self.min_difficulty.or(params.min_difficulty),
+            max_difficulty: self.max_difficulty.or(params.max_difficulty),
+            ..params
+        })
+    }
+
+    // Returns curves without the ones for fields set in params, so that the sidecar's values
+    // win over curves from the command line, and with the sidecar's curves added.
+    pub fn apply_curves(&self, curves: &preset::ParamCurves) -> preset::ParamCurves {
+        let mut ret = curves.clone();
+        for field in self.params.keys() {
+            ret.remove(field);
+        }
+        ret.extend(self.curves.clone());
+        ret
+    }
+}
+
+#[test]
+fn test_sidecar() {
+    let pack = Sidecar::parse(
+        "to_style = [\"itg-doubles\", \"pump-doubles\"]\nmin_difficulty = 5\n[params]\nmax_turn = 2.0\ndoubles_movement = [0.5, 0.002]\n",
+    )
+    .unwrap();
+    let song = Sidecar::parse(
+        "edits = true\n[params]\ndoubles_movement = [1.0, 0.01]\n[curves]\nmax_turn = { steps = [[10, 3.0]] }\n",
+    )
+    .unwrap();
+    let s = pack.merge(song);
+    assert_eq!(
+        s.to_styles(&[Style::HorizonSingles]).unwrap(),
+        vec![Style::ItgDoubles, Style::PumpDoubles]
+    );
+    assert!(s.edits(false));
+    let params = s
+        .apply(GeneratorParameters {
+            max_difficulty: Some(10),
+            ..GeneratorParameters::default()
+        })
+        .unwrap();
+    assert_eq!(params.max_turn, Some(2.0));
+    assert_eq!(params.doubles_movement, Some((1.0, 0.01)));
+    assert_eq!(params.min_difficulty, Some(5));
+    assert_eq!(params.max_difficulty, Some(10));
+    let curves = s.apply_curves(&preset::ParamCurves::default());
+    assert_eq!(curves.resolve(params, 9).unwrap().max_turn, Some(2.0));
+    assert_eq!(curves.resolve(params, 10).unwrap().max_turn, Some(3.0));
+
+    // the command line's curves don't override the sidecar's params
+    let (_, cli_curves) = preset::parse(
+        "[curves]\ndoubles_movement = { steps = [[1, [2.0, 0.1]]] }\nmax_angle = { steps = [[1, 1.0]] }\n",
+        false,
+    )
+    .unwrap();
+    let curves = s.apply_curves(&cli_curves);
+    let p = curves.resolve(params, 12).unwrap();
+    assert_eq!(p.doubles_movement, Some((1.0, 0.01)));
+    assert_eq!(p.max_angle, Some(1.0));
+    assert_eq!(p.max_turn, Some(3.0));
+    let s = Sidecar::parse("[curves]\nmax_angle = { steps = [[1, 1.0]] }\n")
+        .unwrap()
+        .merge(Sidecar::parse("[params]\nmax_angle = 2.0\n").unwrap());
+    let p = s
+        .apply_curves(&preset::ParamCurves::default())
+        .resolve(s.apply(params).unwrap(), 12)
+        .unwrap();
+    assert_eq!(p.max_angle, Some(2.0));
+
+    let empty = Sidecar::default();
+    assert_eq!(
+        empty.to_styles(&[Style::HorizonSingles]).unwrap(),
+        vec![Style::HorizonSingles]
+    );
+    assert!(!empty.edits(false));
+
+    let crossovers = Sidecar::parse("crossovers = 2\nvroom = true\n")
+        .unwrap()
+        .merge(Sidecar::parse("crossovers = 0\n").unwrap());
+    assert_eq!(crossovers.crossovers, Some(0));
+    assert_eq!(crossovers.vroom, Some(true));
+    assert!(Sidecar::parse("crossover = 1").is_err());
+    assert!(Sidecar::parse("crossovers = true").is_err());
+    assert!(
+        Sidecar::parse("to_style = [\"itg-quintuples\"]")
+            .unwrap()
+            .to_styles(&[])
+            .is_err()
+    );
+}
+
+#[test]
+fn test_for_song() {
+    let pack_dir = std::env::temp_dir().join(format!("itggen-sidecar-{}", std::process::id()));
+    let song_dir = pack_dir.join("song");
+    std::fs::create_dir_all(&song_dir).unwrap();
+    std::fs::write(
+        pack_dir.join(SIDECAR_FILE_NAME),
+        "edits = true\nmin_difficulty = 3\n",
+    )
+    .unwrap();
+    std::fs::write(song_dir.join(SIDECAR_FILE_NAME), "edits = false\n").unwrap();
+    let s = Sidecar::for_song(&song_dir.join("song.sm")).unwrap();
+    assert_eq!(s.edits, Some(false));
+    assert_eq!(s.min_difficulty, Some(3));
+    std::fs::write(song_dir.join(SIDECAR_FILE_NAME), "edits = \"no\"\n").unwrap();
+    assert!(Sidecar::for_song(&song_dir.join("song.sm")).is_err());
+    std::fs::remove_dir_all(&pack_dir).unwrap();
+}
@@ -1,36 +1,86 @@
-use crate::generator::{Generator, GeneratorParameters};
+use crate::beam;
+use crate::error::{Error, ParseError, ParseErrorKind};
+use crate::foot::Foot;
+use crate::generator::{Generator, GeneratorParameters, HandsPolicy, Rule};
+use crate::metadata::{self, ChartMetadata};
+use crate::msd;
+use crate::preset::ParamCurves;
 use crate::style::Style;
+use crate::timing::TimingData;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::str::FromStr;
 
-fn to_lines(s: &str) -> Vec<String> {
-    s.lines()
-        .map(|s| {
-            let mut s = s.to_owned();
-            if let Some(p) = s.find("//") {
-                s.truncate(p);
-            }
-            s.trim().to_owned()
-        })
-        .filter(|s| !s.is_empty())
-        .collect()
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Note {
+    Tap,
+    HoldHead,
+    RollHead,
+}
+
+impl Note {
+    fn sm_char(&self) -> char {
+        match self {
+            Note::Tap => '1',
+            Note::HoldHead => '2',
+            Note::RollHead => '4',
+        }
+    }
 }
 
-fn find_start_at(slice: &str, at: usize, pat: &str) -> Option<usize> {
-    slice[at..].find(pat).map(|i| at + i)
+#[derive(Debug, Default, PartialEq)]
+struct Row {
+    steps: Vec<(i8, Note)>,
+    tails: Vec<i8>,
+    mines: Vec<i8>,
 }
 
-fn columns(s: &str, remove_jumps: bool) -> Option<Vec<i8>> {
-    let mut ret = Vec::new();
+// Returns the first unknown character on failure.
+fn parse_row(s: &str) -> Result<Row, char> {
+    let mut ret = Row::default();
     for (i, c) in s.chars().enumerate() {
-        if match c {
-            '1' | '2' | '4' | 'L' => true,
-            '0' | '3' | 'M' | 'F' => false,
-            _ => return None,
-        } {
-            ret.push(i as i8);
+        let col = i as i8;
+        match c {
+            '1' | 'L' => ret.steps.push((col, Note::Tap)),
+            '2' => ret.steps.push((col, Note::HoldHead)),
+            '4' => ret.steps.push((col, Note::RollHead)),
+            '3' => ret.tails.push(col),
+            'M' => ret.mines.push(col),
+            '0' | 'F' => {}
+            _ => return Err(c),
         }
     }
-    ret.truncate(if remove_jumps { 1 } else { 2 }); // max 2 columns
-    Some(ret)
+    Ok(ret)
+}
+
+#[test]
+fn test_parse_row() {
+    assert_eq!(
+        parse_row("1020"),
+        Ok(Row {
+            steps: vec![(0, Note::Tap), (2, Note::HoldHead)],
+            tails: vec![],
+            mines: vec![],
+        })
+    );
+    assert_eq!(
+        parse_row("3401"),
+        Ok(Row {
+            steps: vec![(1, Note::RollHead), (3, Note::Tap)],
+            tails: vec![0],
+            mines: vec![],
+        })
+    );
+    assert_eq!(
+        parse_row("0M0F"),
+        Ok(Row {
+            steps: vec![],
+            tails: vec![],
+            mines: vec![1],
+        })
+    );
+    assert_eq!(parse_row("1111").unwrap().steps.len(), 4);
+    assert_eq!(parse_row("00x0"), Err('x'));
 }
 
 fn params_str(params: GeneratorParameters) -> String {
@@ -42,110 +92,91 @@ fn params_str(params: GeneratorParameters) -> String {
     if !params.disallow_footswitch {
         ret.push('F');
     }
-    if let Some(ma) = params.max_angle {
-        if ma > PI / 2.0 + 0.0001 {
-            ret.push('C');
-            if ma > PI - 0.0001 {
-                ret.push('+');
-            }
+    if let Some(ma) = params.max_angle
+        && ma > PI / 2.0 + 0.0001
+    {
+        ret.push('C');
+        if ma > PI - 0.0001 {
+            ret.push('+');
         }
     }
     ret
 }
 
-fn write_description(
-    chart: &SMChart,
-    params: GeneratorParameters,
-    extra_description: Option<&String>,
-    should_write_from_difficulty: bool,
-) -> String {
+// The description of a chart generated as described by metadata, e.g. "AYEAG(C)[H] - Zaia".
+fn write_description(metadata: &ChartMetadata) -> String {
     let mut ret = String::new();
     ret.push_str("AYEAG");
-    let params_str = params_str(params);
+    let params_str = params_str(metadata.params);
     if !params_str.is_empty() {
         ret.push('(');
         ret.push_str(&params_str);
         ret.push(')');
     }
-    if should_write_from_difficulty {
-        if let Some(c) = chart.difficulty.chars().next() {
-            ret.push('[');
-            ret.push(c);
-            ret.push(']');
-        }
+    if metadata.write_from_difficulty
+        && let Some(c) = metadata.from_difficulty.chars().next()
+    {
+        ret.push('[');
+        ret.push(c);
+        ret.push(']');
     }
-    if let Some(extra_description) = extra_description {
+    if let Some(extra_description) = &metadata.extra_description {
         ret.push_str(" - ");
         ret.push_str(extra_description);
     }
-    if !chart.description.is_empty() {
+    if !metadata.from_description.is_empty() {
         ret.push_str(" - ");
-        ret.push_str(&chart.description);
+        ret.push_str(&metadata.from_description);
     }
     ret
 }
 
-fn write_sm_chart(
-    chart: &SMChart,
-    to_style: Style,
-    params: GeneratorParameters,
-    edit: bool,
-    extra_description: Option<&String>,
-    should_write_from_difficulty: bool,
-    generated_notes: &str,
-) -> String {
+fn write_difficulty(metadata: &ChartMetadata) -> &str {
+    if metadata.edit {
+        "Edit"
+    } else {
+        metadata
+            .to_difficulty
+            .as_ref()
+            .unwrap_or(&metadata.from_difficulty)
+    }
+}
+
+fn write_sm_chart(metadata: &ChartMetadata, generated_notes: &str) -> String {
     let mut ret = String::new();
     ret.push_str("#NOTES:\n");
     ret.push_str("     ");
-    ret.push_str(to_style.sm_string());
+    ret.push_str(metadata.to_style.sm_string());
     ret.push_str(":\n     ");
-    ret.push_str(&write_description(
-        chart,
-        params,
-        extra_description,
-        should_write_from_difficulty,
-    ));
+    ret.push_str(&msd::escape(&write_description(metadata)));
     ret.push_str(":\n     ");
-    ret.push_str(if edit { "Edit" } else { &chart.difficulty });
+    ret.push_str(&msd::escape(write_difficulty(metadata)));
     ret.push_str(":\n     ");
-    ret.push_str(&chart.level.to_string());
+    ret.push_str(&metadata.from_level.to_string());
     ret.push_str(":\n     :\n");
     ret.push_str(generated_notes);
     ret
 }
 
-fn write_ssc_chart(
-    chart: &SMChart,
-    to_style: Style,
-    params: GeneratorParameters,
-    edit: bool,
-    extra_description: Option<&String>,
-    should_write_from_difficulty: bool,
-    generated_notes: &str,
-) -> String {
+fn write_ssc_chart(metadata: &ChartMetadata, generated_notes: &str) -> String {
     let mut ret = String::new();
 
     ret.push_str("#NOTEDATA:;\n");
 
     ret.push_str("#STEPSTYPE:");
-    ret.push_str(to_style.sm_string());
+    ret.push_str(metadata.to_style.sm_string());
     ret.push_str(";\n");
 
     ret.push_str("#DESCRIPTION:");
-    ret.push_str(&write_description(
-        chart,
-        params,
-        extra_description,
-        should_write_from_difficulty,
-    ));
+    ret.push_str(&msd::escape(&write_description(metadata)));
     ret.push_str(";\n");
 
     ret.push_str("#DIFFICULTY:");
-    ret.push_str(if edit { "Edit" } else { &chart.difficulty });
+    ret.push_str(&msd::escape(write_difficulty(metadata)));
     ret.push_str(";\n");
 
     ret.push_str("#METER:");
-    ret.push_str(&chart.level.to_string());
+    ret.push_str(&metadata.from_level.to_string());
     ret.push_str(";\n");
 
     ret.push_str("#NOTES:\n");
@@ -154,13 +185,13 @@ fn write_ssc_chart(
     ret
 }
 
-fn row_notes(cols: &[i8], style: Style) -> String {
+fn row_notes(notes: &[(i8, char)], style: Style) -> String {
     let mut ret = String::new();
     let mut row = "0".repeat(style.num_cols() as usize);
-    for col in cols {
+    for (col, c) in notes {
         for sm_col in style.sm_cols_for_col(*col) {
             let sm_col_usize = sm_col as usize;
-            row.replace_range(sm_col_usize..=sm_col_usize, "1");
+            row.replace_range(sm_col_usize..=sm_col_usize, &c.to_string());
         }
     }
 
@@ -170,278 +201,1767 @@ fn row_notes(cols: &[i8], style: Style) -> String {
     ret
 }
 
-fn chart_hash(chart: &SMChart) -> u64 {
-    use std::hash::{DefaultHasher, Hash, Hasher};
-    let mut s = DefaultHasher::new();
-    chart.notes_lines.hash(&mut s);
-    s.finish()
+// 64-bit FNV-1a, which unlike std's DefaultHasher gives the same hash on every platform and
+// Rust version.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut ret: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        ret ^= *b as u64;
+        ret = ret.wrapping_mul(0x100000001b3);
+    }
+    ret
+}
+
+// Bumped whenever seed_params() changes which fields it writes or how, which changes the seed of
+// every chart.
+const SEED_VERSION: u32 = 1;
+
+// The parameters that affect the seed, one "field=value\n" per field in this fixed order. Values
+// are written like Rust's {:?} writes them (e.g. "Some((1, 0.1))"), and hands as its -hands name.
+// The seed and difficulty range aren't written, and fields added to GeneratorParameters later must
+// only be written when they aren't their default, so that adding one doesn't change every seed.
+fn seed_params(p: &GeneratorParameters) -> String {
+    let fields: [(&str, String); 35] = [
+        (
+            "disallow_footswitch",
+            format!("{:?}", p.disallow_footswitch),
+        ),
+        ("max_repeated", format!("{:?}", p.max_repeated)),
+        ("repeated_decay", format!("{:?}", p.repeated_decay)),
+        (
+            "other_foot_repeat_decay",
+            format!("{:?}", p.other_foot_repeat_decay),
+        ),
+        (
+            "max_dist_between_feet",
+            format!("{:?}", p.max_dist_between_feet),
+        ),
+        (
+            "dist_between_feet_decay",
+            format!("{:?}", p.dist_between_feet_decay),
+        ),
+        (
+            "max_dist_between_feet_if_crossover",
+            format!("{:?}", p.max_dist_between_feet_if_crossover),
+        ),
+        (
+            "max_dist_between_steps",
+            format!("{:?}", p.max_dist_between_steps),
+        ),
+        (
+            "dist_between_steps_decay",
+            format!("{:?}", p.dist_between_steps_decay),
+        ),
+        (
+            "max_horizontal_dist_between_steps",
+            format!("{:?}", p.max_horizontal_dist_between_steps),
+        ),
+        (
+            "horizontal_dist_between_steps_decay",
+            format!("{:?}", p.horizontal_dist_between_steps_decay),
+        ),
+        (
+            "max_horizontal_dist_between_steps_if_crossover",
+            format!("{:?}", p.max_horizontal_dist_between_steps_if_crossover),
+        ),
+        (
+            "max_vertical_dist_between_steps",
+            format!("{:?}", p.max_vertical_dist_between_steps),
+        ),
+        (
+            "vertical_dist_between_steps_decay",
+            format!("{:?}", p.vertical_dist_between_steps_decay),
+        ),
+        (
+            "timed_max_dist_between_steps",
+            format!("{:?}", p.timed_max_dist_between_steps),
+        ),
+        (
+            "timed_max_horizontal_dist_between_steps",
+            format!("{:?}", p.timed_max_horizontal_dist_between_steps),
+        ),
+        (
+            "horizontal_dist_between_3_steps_same_foot_decay",
+            format!("{:?}", p.horizontal_dist_between_3_steps_same_foot_decay),
+        ),
+        (
+            "max_horizontal_dist_between_4_steps_both_feet",
+            format!("{:?}", p.max_horizontal_dist_between_4_steps_both_feet),
+        ),
+        (
+            "max_horizontal_dist_between_3_steps_same_foot",
+            format!("{:?}", p.max_horizontal_dist_between_3_steps_same_foot),
+        ),
+        ("max_angle", format!("{:?}", p.max_angle)),
+        ("angle_decay", format!("{:?}", p.angle_decay)),
+        ("max_turn", format!("{:?}", p.max_turn)),
+        ("turn_decay", format!("{:?}", p.turn_decay)),
+        (
+            "crossover_multiplier",
+            format!("{:?}", p.crossover_multiplier),
+        ),
+        ("max_bar_angle", format!("{:?}", p.max_bar_angle)),
+        ("bar_angle_decay", format!("{:?}", p.bar_angle_decay)),
+        (
+            "preserve_input_repetitions",
+            format!("{:?}", p.preserve_input_repetitions),
+        ),
+        ("doubles_movement", format!("{:?}", p.doubles_movement)),
+        (
+            "doubles_dist_from_side",
+            format!("{:?}", p.doubles_dist_from_side),
+        ),
+        (
+            "doubles_steps_per_dist",
+            format!("{:?}", p.doubles_steps_per_dist),
+        ),
+        (
+            "doubles_track_individual_feet",
+            format!("{:?}", p.doubles_track_individual_feet),
+        ),
+        ("beam_width", format!("{:?}", p.beam_width)),
+        (
+            "disallow_foot_opposite_side",
+            format!("{:?}", p.disallow_foot_opposite_side),
+        ),
+        ("remove_jumps", format!("{:?}", p.remove_jumps)),
+        ("hands", p.hands.to_string()),
+    ];
+    let mut ret = String::new();
+    for (field, value) in fields {
+        ret.push_str(&format!("{}={}\n", field, value));
+    }
+    ret
 }
 
-fn generate_notes(
+// The seed used when none is given: the FNV-1a hash of "itggen seed v<SEED_VERSION>\n", the
+// chart's notes lines (without whitespace and comments, each followed by '\n'), the target
+// style's name (as passed to -o) and '\n', then seed_params().
+fn chart_seed(chart: &SMChart, to_style: Style, params: GeneratorParameters) -> u64 {
+    let mut bytes = format!("itggen seed v{}\n", SEED_VERSION).into_bytes();
+    for l in &chart.notes_lines {
+        bytes.extend_from_slice(l.as_bytes());
+        bytes.push(b'\n');
+    }
+    bytes.extend_from_slice(to_style.name().as_bytes());
+    bytes.push(b'\n');
+    bytes.extend_from_slice(seed_params(&params).as_bytes());
+    fnv1a(&bytes)
+}
+
+#[test]
+fn test_chart_seed() {
+    assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+    assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    let chart = parse_charts(
+        "#NOTES:\n dance-single:\n :\n Easy:\n 1:\n :\n1000 // a\n0100\n;\n",
+        false,
+    )
+    .unwrap()
+    .remove(0);
+    let params = GeneratorParameters::default();
+    let seed = chart_seed(&chart, Style::ItgDoubles, params);
+    assert_eq!(seed, 12195957201476659709);
+    assert_eq!(
+        chart_seed(
+            &chart,
+            Style::ItgDoubles,
+            GeneratorParameters {
+                seed: Some(1),
+                max_difficulty: Some(2),
+                ..params
+            }
+        ),
+        seed
+    );
+    assert_ne!(chart_seed(&chart, Style::PumpDoubles, params), seed);
+    assert_ne!(
+        chart_seed(&chart, Style::PumpMiddleFour, params),
+        chart_seed(&chart, Style::PumpDoubles, params)
+    );
+    assert_ne!(
+        chart_seed(
+            &chart,
+            Style::ItgDoubles,
+            GeneratorParameters {
+                max_turn: Some(1.0),
+                ..params
+            }
+        ),
+        seed
+    );
+    assert!(seed_params(&params).starts_with("disallow_footswitch=false\nmax_repeated=None\n"));
+
+    // the generated output for a fixed input, which changes only when the generator does
+    let contents = "#BPMS:0=120;\n#NOTES:\n dance-single:\n :\n Hard:\n 9:\n :\n1000\n0100\n0010\n0001\n,\n1001\n0110\n1000\n0001\n;\n";
+    let chart = parse_charts(contents, false).unwrap().remove(0);
+    let notes = generate_notes(
+        &chart,
+        &song_timing(contents, false).unwrap(),
+        Style::ItgDoubles,
+        crate::preset::create_params(
+            None,
+            0,
+            false,
+            false,
+            false,
+            true,
+            HandsPolicy::Jumps,
+            None,
+            None,
+        ),
+    )
+    .unwrap();
+    assert_eq!(
+        notes.notes,
+        "00001000\n00010000\n00001000\n01000000\n,\n01001000\n00110000\n00010000\n00100000\n;\n"
+    );
+}
+
+// Returns the number of rows in each measure.
+fn measure_sizes(notes_lines: &[String]) -> Vec<usize> {
+    let mut ret = vec![0];
+    for l in notes_lines {
+        if l == "," {
+            ret.push(0);
+        } else if l != ";" {
+            *ret.last_mut().unwrap() += 1;
+        }
+    }
+    ret
+}
+
+#[test]
+fn test_measure_sizes() {
+    let lines: Vec<String> = ["0000", "0000", ",", "0000", ";"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    assert_eq!(measure_sizes(&lines), vec![2, 1]);
+    assert_eq!(measure_sizes(&[]), vec![0]);
+}
+
+// A row of notes, and when it happens if the chart's timing is known.
+struct TimedRow {
+    // index of the row in the chart
+    index: usize,
+    // line of the row in its file, for warnings
+    line: usize,
+    row: Row,
+    time: Option<f32>,
+}
+
+// Everything that carries over from one row to the next while generating a chart.
+#[derive(Clone)]
+struct ChartState {
+    g: Generator,
+    // input column -> output column of holds/rolls that haven't reached their tail yet
+    holds: HashMap<i8, i8>,
+    // notes of hands and quads that couldn't be placed, and of jumps with remove_jumps
+    dropped: usize,
+    // (line in the file, rule) for every rule relaxed to place a step
+    relaxations: Vec<(usize, Rule)>,
+    // (row index, Generator::mine_cols()) for every mine not on a foot's panel
+    mines: Vec<(usize, Vec<i8>)>,
+}
+
+impl ChartState {
+    // Returns None if a step has no column to go on.
+    fn generate_row(
+        &mut self,
+        timed_row: &TimedRow,
+        params: GeneratorParameters,
+        to_style: Style,
+    ) -> Option<String> {
+        let row = &timed_row.row;
+        let g = &mut self.g;
+        if let Some(time) = timed_row.time {
+            g.set_time(time);
+        }
+        let mut out_notes = Vec::new();
+        for col in &row.tails {
+            // tails of dropped heads have no matching hold
+            if let Some(out_col) = self.holds.remove(col) {
+                g.release(out_col);
+                out_notes.push((out_col, '3'));
+            }
+        }
+        // feet on holds can't step, so what they would take goes to the hands policy
+        let (mut steps, extra_steps) = row.steps.split_at(row.steps.len().min(g.free_feet()));
+        if params.remove_jumps && steps.len() > 1 {
+            self.dropped += steps.len() - 1;
+            steps = &steps[..1];
+        }
+        let is_jump = steps.len() > 1;
+        for (col, note) in steps {
+            let idx = g.try_generate_with_input_col(*col, is_jump)?;
+            if *note != Note::Tap {
+                g.hold_last_step();
+                self.holds.insert(*col, idx);
+            }
+            out_notes.push((idx, note.sm_char()));
+        }
+        for rule in g.take_relaxations() {
+            self.relaxations.push((timed_row.line, rule));
+        }
+        let mut bracket_feet = vec![Foot::Left, Foot::Right];
+        for (col, note) in extra_steps {
+            let taken: Vec<i8> = out_notes.iter().map(|(c, _)| *c).collect();
+            let out_col = match params.hands {
+                HandsPolicy::Jumps => None,
+                HandsPolicy::Brackets => {
+                    let mut out_col = None;
+                    for (i, foot) in bracket_feet.iter().enumerate() {
+                        if let Some(c) = g.bracket_col(*foot, &taken) {
+                            out_col = Some(c);
+                            bracket_feet.remove(i);
+                            break;
+                        }
+                    }
+                    out_col
+                }
+                HandsPolicy::Hands => g.hand_col(&taken),
+            };
+            match out_col {
+                Some(out_col) => {
+                    if *note != Note::Tap {
+                        g.block(out_col);
+                        self.holds.insert(*col, out_col);
+                    }
+                    out_notes.push((out_col, note.sm_char()));
+                }
+                None => self.dropped += 1,
+            }
+        }
+        for col in &row.mines {
+            let taken: Vec<i8> = out_notes.iter().map(|(c, _)| *c).collect();
+            if let Some(out_col) = g.foot_mine_col(*col, &taken) {
+                out_notes.push((out_col, 'M'));
+                continue;
+            }
+            let cols = g.mine_cols(&taken);
+            if let Some(out_col) = cols.first() {
+                out_notes.push((*out_col, 'M'));
+                self.mines.push((timed_row.index, cols));
+            }
+        }
+        Some(row_notes(&out_notes, to_style))
+    }
+}
+
+// Moves every mine in mines, see ChartState, that is on a panel the next row with steps steps
+// on to the first of its other columns that isn't, or removes it if there is none, so that
+// mines never go where the feet are about to step.
+fn move_mines(rows: &mut [String], mines: &[(usize, Vec<i8>)], style: Style) {
+    let is_step = |b: u8| matches!(b, b'1' | b'2' | b'4');
+    let sm_cols = |col: i8| {
+        style
+            .sm_cols_for_col(col)
+            .into_iter()
+            .map(|c| c as usize + style.extra_0s())
+            .collect::<Vec<usize>>()
+    };
+    for (r, cols) in mines {
+        let Some(next) = rows[r + 1..].iter().find(|row| row.bytes().any(is_step)) else {
+            continue;
+        };
+        let next = next.as_bytes().to_vec();
+        if !sm_cols(cols[0]).iter().any(|i| is_step(next[*i])) {
+            continue;
+        }
+        let mut row = std::mem::take(&mut rows[*r]).into_bytes();
+        for i in sm_cols(cols[0]) {
+            row[i] = b'0';
+        }
+        let free = |c: &&i8| {
+            sm_cols(**c)
+                .iter()
+                .all(|i| row[*i] == b'0' && !is_step(next[*i]))
+        };
+        if let Some(c) = cols[1..].iter().find(free) {
+            for i in sm_cols(*c) {
+                row[i] = b'M';
+            }
+        }
+        rows[*r] = String::from_utf8(row).unwrap();
+    }
+}
+
+pub struct GeneratedNotes {
+    // the notes lines of the generated chart
+    pub notes: String,
+    // notes of hands and quads that couldn't be placed, and of jumps with remove_jumps
+    pub dropped: usize,
+    // (line in the file, rule) for every rule relaxed to place a step
+    pub relaxations: Vec<(usize, Rule)>,
+}
+
+// Returns the rows of chart, timed if it or song_timing has BPMs.
+fn timed_rows(chart: &SMChart, song_timing: &TimingData) -> Result<Vec<TimedRow>, Error> {
+    let mut timing = song_timing.clone();
+    for (key, value) in &chart.timing_tags {
+        timing.set_tag(key, value).map_err(|e| {
+            ParseError::new(ParseErrorKind::BadTiming(e), chart.line).in_chart(chart.index)
+        })?;
+    }
+    let measure_sizes = measure_sizes(&chart.notes_lines);
+    let mut measure = 0;
+    let mut row_in_measure = 0;
+    let mut rows = Vec::new();
+    for (l, line) in chart.notes_lines.iter().zip(&chart.notes_line_numbers) {
+        match parse_row(l) {
+            Ok(row) => {
+                let time = timing.has_bpms().then(|| {
+                    let beat = 4.0
+                        * (measure as f32 + row_in_measure as f32 / measure_sizes[measure] as f32);
+                    timing.beat_to_seconds(beat)
+                });
+                row_in_measure += 1;
+                rows.push(TimedRow {
+                    index: rows.len(),
+                    line: *line,
+                    row,
+                    time,
+                });
+            }
+            Err(_) if l == "," || l == ";" => {
+                measure += 1;
+                row_in_measure = 0;
+            }
+            Err(c) => {
+                return Err(ParseError::new(ParseErrorKind::UnknownNote(c), *line)
+                    .in_chart(chart.index)
+                    .into());
+            }
+        }
+    }
+    Ok(rows)
+}
+
+// Generates notes for to_style from chart. song_timing is the timing of the file the chart is
+// in, see song_timing().
+pub fn generate_notes(
     chart: &SMChart,
+    song_timing: &TimingData,
     to_style: Style,
     mut params: GeneratorParameters,
-) -> Result<String, String> {
+) -> Result<GeneratedNotes, Error> {
     let mut ret = String::new();
     if params.seed.is_none() {
-        params.seed = Some(chart_hash(chart));
+        params.seed = Some(chart_seed(chart, to_style, params));
     }
-    let mut g = Generator::new(to_style, params);
-    for l in &chart.notes_lines {
-        if let Some(cols) = columns(l, params.remove_jumps) {
-            let is_jump = cols.len() > 1;
-            let mut out_cols = Vec::new();
-            for col in cols {
-                let idx = g.generate_with_input_col(col, is_jump);
-                out_cols.push(idx);
+    let rows = timed_rows(chart, song_timing)?;
+
+    let mut state = ChartState {
+        g: Generator::new(to_style, params),
+        holds: HashMap::new(),
+        dropped: 0,
+        relaxations: Vec::new(),
+        mines: Vec::new(),
+    };
+    let (mut out_rows, state) =
+        match params.beam_width {
+            Some(width) => beam::beam_search(
+                state,
+                &rows,
+                width,
+                params.seed.unwrap(),
+                |s, row, seed| {
+                    s.g.reseed(seed);
+                    s.generate_row(row, params, to_style)
+                },
+                |s| s.g.log_prob(),
+            )
+            .map_err(|i| {
+                Error::Generate(format!(
+                    "couldn't find valid steps for line {}",
+                    rows[i].line
+                ))
+            })?,
+            None => {
+                let mut out_rows = Vec::new();
+                for row in &rows {
+                    out_rows.push(state.generate_row(row, params, to_style).ok_or(
+                        Error::Generate(format!("no available columns for line {}", row.line)),
+                    )?);
+                }
+                (out_rows, state)
             }
-            ret.push_str(&row_notes(&out_cols, to_style));
-            ret.push('\n');
-        } else if l == "," || l == ";" {
+        };
+
+    move_mines(&mut out_rows, &state.mines, to_style);
+    let mut out_rows = out_rows.into_iter();
+    for l in &chart.notes_lines {
+        if l == "," || l == ";" {
             ret.push_str(l);
-            ret.push('\n');
         } else {
-            return Err(format!("unknown notes line: {}", l));
+            ret.push_str(&out_rows.next().unwrap());
+        }
+        ret.push('\n');
+    }
+    Ok(GeneratedNotes {
+        notes: ret,
+        dropped: state.dropped,
+        relaxations: state.relaxations,
+    })
+}
+
+// What's in a chart, for `itggen analyze`.
+#[derive(Debug, Default, PartialEq)]
+pub struct ChartStats {
+    pub measures: usize,
+    // rows with at least one step
+    pub steps: usize,
+    // rows with two steps
+    pub jumps: usize,
+    // rows with three or more steps
+    pub hands: usize,
+    pub holds: usize,
+    pub rolls: usize,
+    pub mines: usize,
+    // seconds from the first step to the last, if the chart has BPMs
+    pub length: Option<f32>,
+}
+
+pub fn chart_stats(chart: &SMChart, song_timing: &TimingData) -> Result<ChartStats, Error> {
+    let rows = timed_rows(chart, song_timing)?;
+    let mut ret = ChartStats {
+        measures: measure_sizes(&chart.notes_lines).len(),
+        ..Default::default()
+    };
+    let mut times: Vec<f32> = Vec::new();
+    for TimedRow { row, time, .. } in &rows {
+        ret.mines += row.mines.len();
+        if row.steps.is_empty() {
+            continue;
+        }
+        ret.steps += 1;
+        match row.steps.len() {
+            1 => {}
+            2 => ret.jumps += 1,
+            _ => ret.hands += 1,
+        }
+        for (_, note) in &row.steps {
+            match note {
+                Note::HoldHead => ret.holds += 1,
+                Note::RollHead => ret.rolls += 1,
+                Note::Tap => {}
+            }
         }
+        times.extend(time);
+    }
+    if let (Some(first), Some(last)) = (times.first(), times.last()) {
+        ret.length = Some(last - first);
     }
     Ok(ret)
 }
 
-struct SMChart {
+#[test]
+fn test_chart_stats() {
+    let contents = "#BPMS:0=120;\n#NOTES:\n dance-single:\n :\n Hard:\n 9:\n :\n1000\n1100\n2000\n3M00\n,\n0000\n4110\n3000\n0000\n;\n";
+    let charts = parse_charts(contents, false).unwrap();
+    let stats = chart_stats(&charts[0], &song_timing(contents, false).unwrap()).unwrap();
+    assert_eq!(
+        stats,
+        ChartStats {
+            measures: 2,
+            steps: 4,
+            jumps: 1,
+            hands: 1,
+            holds: 1,
+            rolls: 1,
+            mines: 1,
+            length: Some(2.5),
+        }
+    );
+    let charts = parse_charts(&contents.replace("#BPMS:0=120;", ""), false).unwrap();
+    assert_eq!(
+        chart_stats(&charts[0], &TimingData::default())
+            .unwrap()
+            .length,
+        None
+    );
+}
+
+// A chart parsed from an .sm/.ssc file.
+pub struct SMChart {
+    // index of the chart in its file
+    pub index: usize,
+    // line number of the chart's #NOTES/#NOTEDATA
+    pub line: usize,
+    // e.g. "dance-single"
+    pub style: String,
+    pub description: String,
+    pub difficulty: String,
+    pub level: i32,
+    // without whitespace and comments
+    pub notes_lines: Vec<String>,
+    pub notes_line_numbers: Vec<usize>,
+    // chart-specific timing tags of .ssc charts
+    pub timing_tags: Vec<(String, String)>,
+    // byte range of the chart in its file, from its #NOTES/#NOTEDATA to the end of its last value
+    pub span: Range<usize>,
+}
+
+// Charts itggen wrote, which unlike other autogen charts it may replace or remove.
+fn written_by_itggen(description: &str) -> bool {
+    description.starts_with("AYEAG")
+}
+
+// Where StepMania keeps a chart: a song has one chart per style and difficulty, except edits,
+// which are told apart by their description.
+#[derive(Debug, PartialEq)]
+struct ChartSlot {
     style: String,
-    description: String,
     difficulty: String,
-    level: i32,
-    notes_lines: Vec<String>,
+    edit_description: Option<String>,
 }
 
-impl SMChart {
-    fn is_autogen(&self) -> bool {
-        self.description.starts_with("AYEAG") || self.description.starts_with("AUTO")
+impl ChartSlot {
+    fn new(style: &str, difficulty: &str, description: &str) -> Self {
+        let is_edit = difficulty.eq_ignore_ascii_case("Edit");
+        ChartSlot {
+            style: style.trim().to_lowercase(),
+            difficulty: difficulty.to_lowercase(),
+            edit_description: is_edit.then(|| description.to_owned()),
+        }
+    }
+
+    // The slot of a chart generated as described by metadata.
+    fn for_metadata(metadata: &ChartMetadata) -> Self {
+        Self::new(
+            metadata.to_style.sm_string(),
+            write_difficulty(metadata),
+            &write_description(metadata),
+        )
     }
 }
 
-fn parse_sm_chart(contents: &str) -> Result<SMChart, String> {
-    let lines = to_lines(contents);
-    if lines.len() < 6 {
-        return Err("invalid metadata".to_owned());
+impl SMChart {
+    pub fn is_autogen(&self) -> bool {
+        written_by_itggen(&self.description) || self.description.starts_with("AUTO")
     }
 
-    let (metadata, notes) = lines.split_at(6);
-    if metadata[0] != "#NOTES:" {
-        return Err(format!("expected '#NOTES:', got '{}'", metadata[0]));
+    fn slot(&self) -> ChartSlot {
+        ChartSlot::new(&self.style, &self.difficulty, &self.description)
     }
-    let level = metadata[4]
-        .replace(":", "")
-        .parse::<i32>()
-        .map_err(|e| format!("Couldn't parse difficulty: {}", e))?;
-    let mut style = metadata[1].to_owned();
-    if style.pop() != Some(':') {
-        return Err("Invalid style".to_owned());
+
+    // e.g. "dance-double Challenge 17 'AYEAG - Zaia'"
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {} {} '{}'",
+            self.style, self.difficulty, self.level, self.description
+        )
     }
-    let mut description = metadata[2].to_owned();
-    if description.pop() != Some(':') {
-        return Err("Invalid description".to_owned());
+}
+
+// Returns the rows and measure separators of an MSD notes param with their line numbers.
+fn notes_lines(notes: &msd::Param) -> Vec<(usize, String)> {
+    let mut ret = Vec::new();
+    for (line, l) in notes.lines() {
+        for (i, row) in l.split(',').enumerate() {
+            if i > 0 {
+                ret.push((line, ",".to_owned()));
+            }
+            if !row.trim().is_empty() {
+                ret.push((line, row.trim().to_owned()));
+            }
+        }
     }
-    let mut difficulty = metadata[3].to_owned();
-    if difficulty.pop() != Some(':') {
-        return Err("Invalid difficulty".to_owned());
+    ret
+}
+
+fn parse_meter(meter: &msd::Param) -> Result<i32, ParseError> {
+    meter.text.trim().parse::<i32>().map_err(|_| {
+        ParseError::new(
+            ParseErrorKind::BadMeter(meter.text.trim().to_owned()),
+            meter.text_line(),
+        )
+    })
+}
+
+// value is an .sm '#NOTES:style:description:difficulty:meter:radar:notes;'.
+fn parse_sm_chart(value: &msd::Value, index: usize) -> Result<SMChart, ParseError> {
+    if value.params.len() < 7 {
+        return Err(ParseError::new(
+            ParseErrorKind::MissingMetadata,
+            value.line(),
+        ));
+    }
+    if value.truncated {
+        return Err(ParseError::new(
+            ParseErrorKind::MissingSemicolon("NOTES".to_owned()),
+            value.line(),
+        ));
     }
+    let mut notes = notes_lines(&value.params[6]);
+    // the ';' is kept as the last line so that charts are hashed the same as before the
+    // tokenizer
+    notes.push((value.params[6].end_line(), ";".to_owned()));
     Ok(SMChart {
-        style,
-        description,
-        difficulty,
-        level,
-        notes_lines: notes.iter().map(|s| s.to_owned()).collect::<Vec<String>>(),
+        index,
+        line: value.line(),
+        style: value.param(1).to_owned(),
+        description: value.param(2).to_owned(),
+        difficulty: value.param(3).to_owned(),
+        level: parse_meter(&value.params[4])?,
+        notes_line_numbers: notes.iter().map(|(line, _)| *line).collect(),
+        notes_lines: notes.into_iter().map(|(_, s)| s).collect(),
+        timing_tags: Vec::new(),
+        span: value.start..value.end,
     })
 }
 
-fn parse_sm_charts(contents: &str) -> Result<Vec<SMChart>, String> {
+// Returns the values of every chart: the #NOTES of an .sm chart, or the values from an .ssc
+// chart's #NOTEDATA up to the next one.
+fn chart_values(values: &[msd::Value], is_ssc: bool) -> Vec<&[msd::Value]> {
+    if !is_ssc {
+        return values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.key() == "NOTES")
+            .map(|(i, _)| &values[i..i + 1])
+            .collect();
+    }
+    let starts: Vec<usize> = (0..values.len())
+        .filter(|&i| values[i].key() == "NOTEDATA")
+        .collect();
     let mut ret = Vec::new();
-    let mut search_from = 0;
-    while let Some(notes_idx) = find_start_at(contents, search_from, "#NOTES:") {
-        let semicolon_idx = find_start_at(contents, notes_idx, ";")
-            .ok_or("couldn't find semicolon after #NOTES".to_owned())?;
-        let notes_str = &contents[notes_idx..=semicolon_idx];
-        let chart =
-            parse_sm_chart(notes_str).map_err(|e| format!("Couldn't parse chart: {}", e))?;
-        ret.push(chart);
-        search_from = semicolon_idx + 1;
+    for (i, &start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(values.len());
+        ret.push(&values[start..end]);
     }
-    Ok(ret)
+    ret
+}
+
+fn parse_sm_charts(values: &[msd::Value]) -> Result<Vec<SMChart>, ParseError> {
+    chart_values(values, false)
+        .into_iter()
+        .enumerate()
+        .map(|(index, v)| parse_sm_chart(&v[0], index).map_err(|e| e.in_chart(index)))
+        .collect()
 }
 
-fn parse_ssc_chart(contents: &str) -> Result<SMChart, String> {
-    let lines = to_lines(contents);
+// values are the values from an .ssc '#NOTEDATA:;' up to the next one.
+fn parse_ssc_chart(values: &[msd::Value], index: usize) -> Result<SMChart, ParseError> {
+    let line = values[0].line();
     let mut style = None;
     let mut description = None;
     let mut difficulty = None;
     let mut level = None;
-    let mut notes_lines = Vec::new();
-    let mut new_kv = true;
-    let mut cur_key = String::new();
-    let mut cur_val_lines = Vec::new();
-    for mut line in lines {
-        if new_kv {
-            if line.remove(0) != '#' {
-                return Err("New key value line didn't start with #".to_owned());
+    let mut notes = Vec::new();
+    let mut timing_tags = Vec::new();
+    for value in values {
+        let key = value.key();
+        match key.as_str() {
+            "STEPSTYPE" => style = Some(value.param(1).to_owned()),
+            "DESCRIPTION" => description = Some(value.param(1).to_owned()),
+            "DIFFICULTY" => difficulty = Some(value.param(1).to_owned()),
+            "METER" => {
+                if let Some(meter) = value.params.get(1) {
+                    level = Some(parse_meter(meter)?);
+                }
             }
-            let semicolon_idx = line.find(':').ok_or("couldn't find semicolon".to_owned())?;
-            let (key, val) = line.split_at(semicolon_idx);
-            cur_key = key.to_owned();
-            line = val[1..].to_owned();
-        }
-        new_kv = line.ends_with(';');
-        if new_kv {
-            line.pop();
-        }
-        if !line.is_empty() {
-            cur_val_lines.push(line);
-        }
-        if new_kv {
-            match cur_key.as_str() {
-                "STEPSTYPE" => {
-                    if cur_val_lines.len() != 1 {
-                        return Err("STEPSTYPE should be one line".to_owned());
-                    }
-                    style = Some(cur_val_lines.pop().unwrap());
+            "NOTES" => {
+                if let Some(p) = value.params.get(1) {
+                    notes = notes_lines(p);
                 }
-                "DESCRIPTION" => {
-                    if cur_val_lines.is_empty() {
-                        description = Some("".to_owned());
-                    } else if cur_val_lines.len() == 1 {
-                        description = Some(cur_val_lines.pop().unwrap());
-                    } else {
-                        return Err("DESCRIPTION should be one line".to_owned());
-                    }
+            }
+            "OFFSET" | "BPMS" | "STOPS" | "DELAYS" | "WARPS" => {
+                timing_tags.push((key, value.param(1).to_owned()));
+            }
+            _ => {}
+        }
+    }
+    let missing_tag = |tag: &str| ParseError::new(ParseErrorKind::MissingTag(tag.to_owned()), line);
+    Ok(SMChart {
+        index,
+        line,
+        style: style.ok_or_else(|| missing_tag("STEPSTYPE"))?,
+        description: description.ok_or_else(|| missing_tag("DESCRIPTION"))?,
+        difficulty: difficulty.ok_or_else(|| missing_tag("DIFFICULTY"))?,
+        level: level.ok_or_else(|| missing_tag("METER"))?,
+        notes_line_numbers: notes.iter().map(|(line, _)| *line).collect(),
+        notes_lines: notes.into_iter().map(|(_, s)| s).collect(),
+        timing_tags,
+        span: values[0].start..values[values.len() - 1].end,
+    })
+}
+
+fn parse_ssc_charts(values: &[msd::Value]) -> Result<Vec<SMChart>, ParseError> {
+    chart_values(values, true)
+        .into_iter()
+        .enumerate()
+        .map(|(index, v)| parse_ssc_chart(v, index).map_err(|e| e.in_chart(index)))
+        .collect()
+}
+
+// Returns the song-wide timing of an .sm/.ssc file.
+pub fn song_timing(contents: &str, is_ssc: bool) -> Result<TimingData, Error> {
+    let first_chart_key = if is_ssc { "NOTEDATA" } else { "NOTES" };
+    let values = msd::parse(contents);
+    Ok(TimingData::from_values(
+        values.iter().take_while(|v| v.key() != first_chart_key),
+    )?)
+}
+
+pub fn parse_charts(contents: &str, is_ssc: bool) -> Result<Vec<SMChart>, Error> {
+    let values = msd::parse(contents);
+    Ok(if is_ssc {
+        parse_ssc_charts(&values)
+    } else {
+        parse_sm_charts(&values)
+    }?)
+}
+
+#[test]
+fn test_parse_charts() {
+    // everything on one line, with escapes and comments
+    let charts =
+        parse_charts("#NOTES:dance-single:a\\:b // c\n:Hard:9::1000,0100;", false).unwrap();
+    assert_eq!(charts[0].description, "a:b");
+    assert_eq!(charts[0].difficulty, "Hard");
+    assert_eq!(charts[0].level, 9);
+    assert_eq!(charts[0].notes_lines, vec!["1000", ",", "0100", ";"]);
+    assert_eq!(charts[0].notes_line_numbers, vec![2, 2, 2, 2]);
+}
+
+// Files from testdata/msd, each with something the MSD format allows that's easy to get wrong.
+#[test]
+fn test_corpus() {
+    let params = GeneratorParameters {
+        seed: Some(1),
+        ..GeneratorParameters::default()
+    };
+    let parse = |contents: &str, is_ssc: bool| {
+        let timing = song_timing(contents, is_ssc).unwrap();
+        let charts = parse_charts(contents, is_ssc).unwrap();
+        for c in &charts {
+            assert!(generate_notes(c, &timing, Style::ItgDoubles, params).is_ok());
+        }
+        (timing, charts)
+    };
+
+    let (timing, charts) = parse(include_str!("../testdata/msd/inline_fields.sm"), false);
+    assert_eq!(timing.bpms, vec![(0.0, 150.0)]);
+    assert_eq!(charts.len(), 2);
+    assert_eq!(charts[0].description, "Tom");
+    assert_eq!(charts[0].difficulty, "Challenge");
+    assert_eq!(charts[0].level, 10);
+    assert_eq!(
+        charts[0].notes_lines,
+        vec!["1000", "0100", ",", "0010", ",", "0001", ";"]
+    );
+    assert_eq!(charts[0].notes_line_numbers, vec![4, 5, 6, 7, 7, 7, 8]);
+    assert_eq!(charts[1].description, "");
+    assert_eq!(charts[1].level, 3);
+    assert_eq!(charts[1].notes_lines, vec!["1000", ",", "0100", ";"]);
+
+    let contents = include_str!("../testdata/msd/escapes.sm");
+    let (timing, charts) = parse(contents, false);
+    let values = msd::parse(contents);
+    assert_eq!(values[0].param(1), "Escapes ; Colons");
+    assert_eq!(values[1].param(1), "http://example.com");
+    assert_eq!(timing.bpms, vec![(0.0, 120.0)]);
+    assert_eq!(charts[0].description, "Tom:Jerry \\ #1");
+    assert_eq!(
+        charts[0].notes_lines,
+        vec!["1000", "0100", "0010", "0001", ";"]
+    );
+    assert_eq!(charts[0].notes_line_numbers[0], 11);
+    // descriptions are escaped when written
+    let g = generate(
+        contents,
+        Style::ItgSingles,
+        Style::ItgDoubles,
+        params,
+        &ParamCurves::default(),
+        DifficultyPolicy::Same,
+        None,
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        parse_charts(&g, false).unwrap()[0].description,
+        "AYEAG(F) - Tom:Jerry \\ #1"
+    );
+
+    let (timing, charts) = parse(include_str!("../testdata/msd/missing_semicolon.sm"), false);
+    assert_eq!(timing.offset, -0.25);
+    assert_eq!(timing.bpms, vec![(0.0, 120.0), (4.0, 240.0)]);
+    assert_eq!(charts.len(), 1);
+    assert_eq!(charts[0].difficulty, "Medium");
+    assert_eq!(charts[0].level, 5);
+
+    let (timing, charts) = parse(include_str!("../testdata/msd/comments.ssc"), true);
+    assert_eq!(timing.bpms, vec![(0.0, 120.0)]);
+    assert_eq!(charts.len(), 1);
+    assert_eq!(charts[0].description, "Nested");
+    assert_eq!(charts[0].level, 12);
+    assert_eq!(charts[0].notes_lines, vec!["1001", "0000", "0100", "0010"]);
+    assert_eq!(
+        charts[0].timing_tags,
+        vec![("BPMS".to_owned(), "0=180".to_owned())]
+    );
+
+    let (timing, charts) = parse(include_str!("../testdata/msd/crlf_bom.ssc"), true);
+    assert_eq!(timing.offset, 0.5);
+    assert_eq!(charts.len(), 2);
+    assert_eq!(charts[0].line, 4);
+    assert_eq!(charts[0].description, "Windows");
+    assert_eq!(
+        charts[0].notes_lines,
+        vec!["1000", "0100", ",", "0010", "0001"]
+    );
+    assert_eq!(charts[1].description, "");
+    assert_eq!(charts[1].difficulty, "Easy");
+}
+
+#[test]
+fn test_parse_errors() {
+    let parse_error = |contents: &str, is_ssc: bool| match parse_charts(contents, is_ssc) {
+        Err(Error::Parse(e)) => e,
+        _ => panic!("expected a parse error"),
+    };
+    let sm = "#TITLE:a;\n#NOTES:\n dance-single:\n :\n Hard:\n 9:\n :\n0000\n;\n#NOTES:\n dance-single:\n :\n Hard:\n nine:\n :\n0000\n;\n";
+    let e = parse_error(sm, false);
+    assert_eq!(e.kind, ParseErrorKind::BadMeter("nine".to_owned()));
+    assert_eq!(e.line, Some(14));
+    assert_eq!(e.chart, Some(1));
+    assert_eq!(
+        e.to_string(),
+        "line 14: couldn't parse meter 'nine' (chart 2)"
+    );
+    let e = parse_error("#NOTES:\n dance-single:\n :\n;", false);
+    assert_eq!(e.kind, ParseErrorKind::MissingMetadata);
+    assert_eq!(e.line, Some(1));
+    let e = parse_error(
+        "#TITLE:a;\n#NOTES:\n dance-single:\n :\n Hard:\n 9:\n :\n0000\n",
+        false,
+    );
+    assert_eq!(e.kind, ParseErrorKind::MissingSemicolon("NOTES".to_owned()));
+    assert_eq!(e.line, Some(2));
+
+    let ssc = "#TITLE:a;\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n// comment\n#DESCRIPTION:a;\n#DIFFICULTY:Hard;\n#METER:\n3x;\n#NOTES:\n0000\n;\n";
+    let e = parse_error(ssc, true);
+    assert_eq!(e.kind, ParseErrorKind::BadMeter("3x".to_owned()));
+    assert_eq!(e.line, Some(8));
+    assert_eq!(e.chart, Some(0));
+
+    let charts = parse_charts(
+        "#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DESCRIPTION:;\n#DIFFICULTY:Hard;\n#METER:3;\n#NOTES:\n0000\n0x00\n;\n",
+        true,
+    )
+    .unwrap();
+    assert_eq!(charts[0].notes_line_numbers, vec![7, 8]);
+    let g = generate_notes(
+        &charts[0],
+        &TimingData::default(),
+        Style::ItgSingles,
+        GeneratorParameters::default(),
+    );
+    assert!(
+        matches!(g, Err(Error::Parse(e)) if e.to_string() == "line 8: unknown note 'x' (chart 1)")
+    );
+
+    let e = match song_timing("#TITLE:a;\n// #BPMS:x;\n#BPMS:0=120\n,4=x;\n#NOTES:", false) {
+        Err(Error::Parse(e)) => e,
+        _ => panic!("expected a parse error"),
+    };
+    assert_eq!(e.line, Some(3));
+    assert_eq!(e.chart, None);
+}
+
+// Writes a chart generated from chart as described by metadata, with the given notes.
+pub fn write_chart(metadata: &ChartMetadata, notes: &str, is_ssc: bool) -> String {
+    if is_ssc {
+        write_ssc_chart(metadata, notes)
+    } else {
+        write_sm_chart(metadata, notes)
+    }
+}
+
+// A generated chart, and how to generate it again.
+pub struct GeneratedChart {
+    pub text: String,
+    pub metadata: ChartMetadata,
+    // notes of hands and quads that couldn't be placed, and of jumps with remove_jumps
+    pub dropped: usize,
+    // (line in the file, rule) for every rule relaxed to place a step
+    pub relaxations: Vec<(usize, Rule)>,
+}
+
+// A chart that wasn't generated from, and why.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Skipped {
+    // a chart of a style that isn't generated from, by STEPSTYPE
+    Style(String),
+    // a chart written by itggen or another generator
+    Autogen,
+    // another chart of a difficulty already generated from, with SourcePolicy::SameDifficulty
+    SecondChart(String),
+    // a difficulty the target style already has a chart for, with DifficultyPolicy::FillGaps,
+    // as (difficulty, STEPSTYPE)
+    Taken(String, String),
+}
+
+impl std::fmt::Display for Skipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Skipped::Style(style) => write!(f, "skipping {} chart", style),
+            Skipped::Autogen => f.write_str("skipping existing autogen chart"),
+            Skipped::SecondChart(difficulty) => {
+                write!(f, "skipping second {} chart", difficulty)
+            }
+            Skipped::Taken(difficulty, style) => write!(
+                f,
+                "skipping {}, there already is a {} {} chart",
+                difficulty, style, difficulty
+            ),
+        }
+    }
+}
+
+// Generates the chart described by metadata from chart.
+fn generate_chart(
+    chart: &SMChart,
+    song_timing: &TimingData,
+    metadata: ChartMetadata,
+    is_ssc: bool,
+) -> Result<GeneratedChart, Error> {
+    let generated = generate_notes(chart, song_timing, metadata.to_style, metadata.params)?;
+    Ok(GeneratedChart {
+        text: write_chart(&metadata, &generated.notes, is_ssc),
+        metadata,
+        dropped: generated.dropped,
+        relaxations: generated.relaxations,
+    })
+}
+
+// Which difficulties generated charts get when the target style may already have charts.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DifficultyPolicy {
+    // the difficulty of the chart generated from, refusing to generate anything if the target
+    // style already has charts that aren't edits
+    Same,
+    // the difficulty of the chart generated from, skipping the difficulties the target style
+    // already has a chart for
+    FillGaps,
+    // like FillGaps, but generating edits instead of skipping
+    FillGapsOrEdit,
+    // always Edit
+    Edit,
+}
+
+// Which charts of a song to generate from when there are several source styles.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SourcePolicy {
+    // every chart of the first listed style the song has charts for
+    #[default]
+    FirstStyle,
+    // for each difficulty, the chart from the first listed style that has one
+    SameDifficulty,
+    // every chart of the first listed style the song has charts for, with the difficulties it is
+    // missing filled in from the other styles by meter
+    ClosestMeter,
+}
+
+#[derive(Debug)]
+pub struct SourcePolicyParseError(String);
+
+impl FromStr for SourcePolicy {
+    type Err = SourcePolicyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-style" => Ok(SourcePolicy::FirstStyle),
+            "same-difficulty" => Ok(SourcePolicy::SameDifficulty),
+            "closest-meter" => Ok(SourcePolicy::ClosestMeter),
+            _ => Err(SourcePolicyParseError(s.to_owned())),
+        }
+    }
+}
+
+impl std::fmt::Display for SourcePolicyParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not parse source policy '{}'", self.0)
+    }
+}
+
+// The difficulties a song has one chart each of, easiest first.
+const DIFFICULTIES: [&str; 5] = ["Beginner", "Easy", "Medium", "Hard", "Challenge"];
+
+fn difficulty_index(difficulty: &str) -> Option<usize> {
+    DIFFICULTIES
+        .iter()
+        .position(|d| d.eq_ignore_ascii_case(difficulty))
+}
+
+// A chart to generate from.
+struct Source {
+    chart: SMChart,
+    // the listed source style the chart is of
+    style: Style,
+    // the difficulty to write instead of the chart's own
+    difficulty: Option<String>,
+}
+
+// Picks the charts to generate from out of charts, which are each of one of from_styles, keeping
+// them in the order they are in the file. Adds the charts of the first style that aren't picked
+// to skipped.
+fn select_sources(
+    charts: Vec<(SMChart, Style)>,
+    from_styles: &[Style],
+    policy: SourcePolicy,
+    skipped: &mut Vec<Skipped>,
+) -> Vec<Source> {
+    let Some(primary) = from_styles
+        .iter()
+        .copied()
+        .find(|s| charts.iter().any(|(_, style)| style == s))
+    else {
+        return Vec::new();
+    };
+    // (index into charts, style) in the order styles are listed, so that earlier ones win
+    let by_style: Vec<(usize, Style)> = from_styles
+        .iter()
+        .flat_map(|s| {
+            let charts = &charts;
+            (0..charts.len()).filter_map(move |i| (charts[i].1 == *s).then_some((i, *s)))
+        })
+        .collect();
+    let is_edit = |i: usize| charts[i].0.difficulty.eq_ignore_ascii_case("Edit");
+    let mut chosen: Vec<bool> = charts.iter().map(|(_, s)| *s == primary).collect();
+    let mut difficulties = vec![None; charts.len()];
+    match policy {
+        SourcePolicy::FirstStyle => {}
+        SourcePolicy::SameDifficulty => {
+            let mut have = Vec::new();
+            for &(i, style) in &by_style {
+                if is_edit(i) {
+                    continue;
                 }
-                "DIFFICULTY" => {
-                    if cur_val_lines.len() != 1 {
-                        return Err("DIFFICULTY should be one line".to_owned());
-                    }
-                    difficulty = Some(cur_val_lines.pop().unwrap());
+                let difficulty = charts[i].0.difficulty.to_lowercase();
+                chosen[i] = !have.contains(&difficulty);
+                if chosen[i] {
+                    have.push(difficulty);
+                } else if style == primary {
+                    skipped.push(Skipped::SecondChart(charts[i].0.difficulty.clone()));
                 }
-                "METER" => {
-                    if cur_val_lines.len() != 1 {
-                        return Err("METER should be one line".to_owned());
-                    }
-                    level = Some(
-                        cur_val_lines
-                            .pop()
-                            .unwrap()
-                            .parse::<i32>()
-                            .map_err(|e| format!("Couldn't parse METER: {}", e))?,
-                    );
+            }
+        }
+        SourcePolicy::ClosestMeter => {
+            let mut meters = [None; DIFFICULTIES.len()];
+            for (c, _) in charts.iter().filter(|(_, s)| *s == primary) {
+                if let Some(d) = difficulty_index(&c.difficulty) {
+                    meters[d].get_or_insert(c.level);
                 }
-                "NOTES" => {
-                    notes_lines = cur_val_lines;
+            }
+            let Some(easiest) = meters.iter().position(Option::is_some) else {
+                return Vec::new();
+            };
+            let hardest = meters.iter().rposition(Option::is_some).unwrap();
+            // gaps between the primary style's charts first, then outwards from them
+            let order: Vec<usize> = (easiest..hardest)
+                .filter(|&d| meters[d].is_none())
+                .chain((0..easiest).rev())
+                .chain(hardest + 1..DIFFICULTIES.len())
+                .collect();
+            for d in order {
+                let lower = meters[..d].iter().rev().find_map(|m| *m);
+                let upper = meters[d + 1..].iter().find_map(|m| *m);
+                let target = match (lower, upper) {
+                    (Some(l), Some(u)) => (l + u) as f32 / 2.0,
+                    (Some(m), None) | (None, Some(m)) => m as f32,
+                    (None, None) => continue,
+                };
+                let best = by_style
+                    .iter()
+                    .map(|&(i, _)| i)
+                    .filter(|&i| {
+                        let level = charts[i].0.level;
+                        !chosen[i]
+                            && !is_edit(i)
+                            && lower.is_none_or(|l| level > l)
+                            && upper.is_none_or(|u| level < u)
+                    })
+                    .min_by(|&a, &b| {
+                        let distance = |i: usize| (charts[i].0.level as f32 - target).abs();
+                        distance(a).total_cmp(&distance(b))
+                    });
+                if let Some(i) = best {
+                    chosen[i] = true;
+                    meters[d] = Some(charts[i].0.level);
+                    if !charts[i].0.difficulty.eq_ignore_ascii_case(DIFFICULTIES[d]) {
+                        difficulties[i] = Some(DIFFICULTIES[d].to_owned());
+                    }
                 }
-                _ => {}
             }
-            cur_val_lines = Default::default();
         }
     }
-    let style = style.ok_or("No STEPSTYPE")?;
-    let description = description.ok_or("No DESCRIPTION")?;
-    let difficulty = difficulty.ok_or("No DIFFICULTY")?;
-    let level = level.ok_or("No METER")?;
-    Ok(SMChart {
-        style,
-        description,
-        difficulty,
-        level,
-        notes_lines,
-    })
+    charts
+        .into_iter()
+        .zip(chosen.into_iter().zip(difficulties))
+        .filter(|(_, (chosen, _))| *chosen)
+        .map(|((chart, style), (_, difficulty))| Source {
+            chart,
+            style,
+            difficulty,
+        })
+        .collect()
+}
+
+// Parameters in curves are resolved per chart, from the chart's level. Returns the generated
+// charts, and the charts that weren't generated from.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_charts(
+    contents: &str,
+    from_style: Style,
+    to_style: Style,
+    params: GeneratorParameters,
+    curves: &ParamCurves,
+    difficulties: DifficultyPolicy,
+    extra_description: Option<&String>,
+    is_ssc: bool,
+) -> Result<(Vec<GeneratedChart>, Vec<Skipped>), Error> {
+    generate_charts_from_styles(
+        contents,
+        &[from_style],
+        SourcePolicy::FirstStyle,
+        to_style,
+        params,
+        curves,
+        difficulties,
+        extra_description,
+        is_ssc,
+    )
 }
 
-fn parse_ssc_charts(contents: &str) -> Result<Vec<SMChart>, String> {
+// Like generate_charts(), but generating from the charts of from_styles picked by sources.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_charts_from_styles(
+    contents: &str,
+    from_styles: &[Style],
+    sources: SourcePolicy,
+    to_style: Style,
+    params: GeneratorParameters,
+    curves: &ParamCurves,
+    difficulties: DifficultyPolicy,
+    extra_description: Option<&String>,
+    is_ssc: bool,
+) -> Result<(Vec<GeneratedChart>, Vec<Skipped>), Error> {
     let mut ret = Vec::new();
-    let mut notedata_idx = match find_start_at(contents, 0, "#NOTEDATA:") {
-        Some(i) => i,
-        None => {
-            return Ok(Vec::new());
+    let mut skipped = Vec::new();
+    let song_timing = song_timing(contents, is_ssc)?;
+    let mut charts = Vec::new();
+    // difficulties the target style already has a chart for
+    let mut taken = Vec::new();
+    for chart in parse_charts(contents, is_ssc)? {
+        // charts itggen wrote are replaced, see write_generated()
+        if difficulties != DifficultyPolicy::Edit
+            && to_style.is_sm_string(&chart.style)
+            && !chart.difficulty.eq_ignore_ascii_case("Edit")
+            && !written_by_itggen(&chart.description)
+        {
+            if difficulties == DifficultyPolicy::Same {
+                return Err(Error::Generate(format!(
+                    "already contains {} charts",
+                    to_style.sm_string()
+                )));
+            }
+            taken.push(chart.difficulty.to_lowercase());
+        }
+        let Some(&from_style) = from_styles.iter().find(|s| s.is_sm_string(&chart.style)) else {
+            skipped.push(Skipped::Style(chart.style.clone()));
+            continue;
+        };
+        if chart.is_autogen() {
+            skipped.push(Skipped::Autogen);
+            continue;
+        }
+        if let Some(ignore) = params.min_difficulty
+            && chart.level < ignore
+        {
+            continue;
+        }
+        if let Some(ignore) = params.max_difficulty
+            && chart.level > ignore
+        {
+            continue;
         }
-    };
-    loop {
-        match find_start_at(contents, notedata_idx + 1, "#NOTEDATA:") {
-            Some(next_notedata) => {
-                let chart_str = &contents[notedata_idx..next_notedata];
-                let chart = parse_ssc_chart(chart_str)
-                    .map_err(|e| format!("Couldn't parse chart: {}", e))?;
-                ret.push(chart);
-                notedata_idx = next_notedata;
-            }
-            None => {
-                let chart_str = &contents[notedata_idx..];
-                let chart = parse_ssc_chart(chart_str)
-                    .map_err(|e| format!("Couldn't parse chart: {}", e))?;
-                ret.push(chart);
-                break;
+        charts.push((chart, from_style));
+    }
+    // (source, whether to generate an edit from it)
+    let mut charts_edit = Vec::new();
+    for source in select_sources(charts, from_styles, sources, &mut skipped) {
+        let difficulty = source
+            .difficulty
+            .as_ref()
+            .unwrap_or(&source.chart.difficulty);
+        let is_taken = taken.contains(&difficulty.to_lowercase());
+        let edit = match difficulties {
+            DifficultyPolicy::Same => false,
+            DifficultyPolicy::Edit => true,
+            _ if !is_taken => false,
+            DifficultyPolicy::FillGaps => {
+                skipped.push(Skipped::Taken(
+                    difficulty.clone(),
+                    to_style.sm_string().to_owned(),
+                ));
+                continue;
             }
+            DifficultyPolicy::FillGapsOrEdit => true,
+        };
+        charts_edit.push((source, edit));
+    }
+    let num_edits = charts_edit.iter().filter(|(_, edit)| *edit).count();
+    for (source, edit) in &charts_edit {
+        let (chart, edit) = (&source.chart, *edit);
+        let mut params = curves.resolve(params, chart.level)?;
+        if params.seed.is_none() {
+            params.seed = Some(chart_seed(chart, to_style, params));
         }
+        let metadata = ChartMetadata {
+            version: metadata::VERSION.to_owned(),
+            from_style: source.style,
+            from_difficulty: chart.difficulty.clone(),
+            from_description: chart.description.clone(),
+            from_level: chart.level,
+            to_style,
+            to_difficulty: source.difficulty.clone(),
+            edit,
+            extra_description: extra_description.cloned(),
+            write_from_difficulty: num_edits > 1 && edit,
+            params,
+        };
+        ret.push(generate_chart(chart, &song_timing, metadata, is_ssc)?);
     }
-    Ok(ret)
+
+    Ok((ret, skipped))
 }
 
+// Like generate_charts(), but returns just the text of the charts.
+#[allow(clippy::too_many_arguments)]
 pub fn generate(
     contents: &str,
     from_style: Style,
     to_style: Style,
     params: GeneratorParameters,
-    edit: bool,
+    curves: &ParamCurves,
+    difficulties: DifficultyPolicy,
     extra_description: Option<&String>,
     is_ssc: bool,
-) -> Result<String, String> {
-    let mut ret = String::new();
-    let mut charts = Vec::new();
-    for chart in if is_ssc {
-        parse_ssc_charts(contents)
-    } else {
-        parse_sm_charts(contents)
-    }? {
-        if !edit && chart.style == to_style.sm_string() && chart.difficulty != "Edit" {
-            return Err(format!("already contains {} charts", to_style.sm_string()));
-        }
-        if chart.style != from_style.sm_string() {
-            println!("  skipping {} chart", chart.style);
-            continue;
-        }
-        if chart.is_autogen() {
-            println!("  skipping existing autogen chart");
-            continue;
+) -> Result<String, Error> {
+    Ok(generate_charts(
+        contents,
+        from_style,
+        to_style,
+        params,
+        curves,
+        difficulties,
+        extra_description,
+        is_ssc,
+    )?
+    .0
+    .into_iter()
+    .map(|c| c.text)
+    .collect())
+}
+
+// Generates the chart described by metadata again, from the chart in contents it was generated
+// from. write_generated() puts it back in its slot.
+pub fn regenerate_chart(
+    contents: &str,
+    metadata: &ChartMetadata,
+    is_ssc: bool,
+) -> Result<GeneratedChart, Error> {
+    let song_timing = song_timing(contents, is_ssc)?;
+    let chart = parse_charts(contents, is_ssc)?
+        .into_iter()
+        .find(|c| {
+            !c.is_autogen()
+                && metadata.from_style.is_sm_string(&c.style)
+                && c.difficulty == metadata.from_difficulty
+                && c.description == metadata.from_description
+                && c.level == metadata.from_level
+        })
+        .ok_or(Error::Generate(format!(
+            "couldn't find the {} {} chart it was generated from",
+            metadata.from_style.sm_string(),
+            metadata.from_difficulty
+        )))?;
+    generate_chart(&chart, &song_timing, metadata.clone(), is_ssc)
+}
+
+// Writes generated charts into contents, returning the new contents and the metadata of the
+// generated charts in them, given the metadata of the ones already there. A chart replaces the
+// chart itggen wrote in its slot (see ChartSlot) where it is, or is appended if there is none.
+// With remove_autogen, the other charts itggen wrote are removed. Other charts are never changed.
+pub fn write_generated(
+    contents: &str,
+    mut metadata: Vec<ChartMetadata>,
+    charts: Vec<GeneratedChart>,
+    remove_autogen: bool,
+    is_ssc: bool,
+) -> Result<(String, Vec<ChartMetadata>), Error> {
+    let old: Vec<SMChart> = parse_charts(contents, is_ssc)?
+        .into_iter()
+        .filter(|c| written_by_itggen(&c.description))
+        .collect();
+    if remove_autogen {
+        metadata.clear();
+    }
+    let mut replaced = vec![None; old.len()];
+    let mut appended = String::new();
+    for c in charts {
+        let slot = ChartSlot::for_metadata(&c.metadata);
+        match old.iter().position(|o| o.slot() == slot) {
+            Some(i) => replaced[i] = Some(c.text),
+            None => appended.push_str(&c.text),
         }
-        if let Some(ignore) = params.min_difficulty {
-            if chart.level < ignore {
-                continue;
+        metadata.retain(|m| ChartSlot::for_metadata(m) != slot);
+        metadata.push(c.metadata);
+    }
+    let mut ret = String::new();
+    let mut at = 0;
+    for (o, text) in old.iter().zip(replaced) {
+        match text {
+            Some(text) => {
+                ret.push_str(&contents[at..o.span.start]);
+                ret.push_str(text.trim_end());
+                at = o.span.end;
             }
-        }
-        if let Some(ignore) = params.max_difficulty {
-            if chart.level > ignore {
-                continue;
+            None if remove_autogen => {
+                ret.push_str(&contents[at..o.span.start]);
+                at = skip_rest_of_line(contents, o.span.end);
             }
+            None => {}
         }
-        charts.push(chart);
     }
-    for chart in &charts {
-        let generated_notes = generate_notes(chart, to_style, params)?;
-        let write_fn = if is_ssc {
-            write_ssc_chart
-        } else {
-            write_sm_chart
+    ret.push_str(&contents[at..]);
+    if !appended.is_empty() {
+        ret.push('\n');
+        ret.push_str(&appended);
+    }
+    Ok((ret, metadata))
+}
+
+#[test]
+fn test_write_generated() {
+    for is_ssc in [false, true] {
+        let chart = |style: &str, description: &str, difficulty: &str, notes: &str| {
+            if is_ssc {
+                format!(
+                    "#NOTEDATA:;\n#STEPSTYPE:{};\n#DESCRIPTION:{};\n#DIFFICULTY:{};\n#METER:9;\n#NOTES:\n{}\n;\n",
+                    style, description, difficulty, notes
+                )
+            } else {
+                format!(
+                    "#NOTES:\n     {}:\n     {}:\n     {}:\n     9:\n     :\n{}\n;\n",
+                    style, description, difficulty, notes
+                )
+            }
         };
-        ret.push_str(&write_fn(
-            chart,
-            to_style,
-            params,
-            edit,
-            extra_description,
-            charts.len() > 1 && edit,
-            &generated_notes,
-        ));
-        println!("  generated for {}", chart.difficulty);
+        let human = [
+            chart("dance-single", "a", "Hard", "1000\n0100"),
+            chart("dance-single", "b", "Challenge", "0010\n0001"),
+        ];
+        let orig = [
+            "#BPMS:0=120;\n",
+            &human[0],
+            &chart("dance-double", "AYEAG - a", "Hard", "10000000\n10000000"),
+            &chart("dance-double", "AYEAG - old", "Edit", "10000000"),
+            &human[1],
+        ]
+        .concat();
+        let generate = |contents: &str| {
+            generate_charts(
+                contents,
+                Style::ItgSingles,
+                Style::ItgDoubles,
+                GeneratorParameters::default(),
+                &ParamCurves::default(),
+                DifficultyPolicy::Same,
+                None,
+                is_ssc,
+            )
+            .unwrap()
+            .0
+        };
+        let charts = generate(&orig);
+        let old_metadata = vec![charts[0].metadata.clone()];
+        let (contents, metadata) =
+            write_generated(&orig, old_metadata, generate(&orig), false, is_ssc).unwrap();
+        let summaries = |contents: &str| {
+            parse_charts(contents, is_ssc)
+                .unwrap()
+                .iter()
+                .map(|c| format!("{} {}", c.style, c.difficulty))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            summaries(&contents),
+            vec![
+                "dance-single Hard",
+                "dance-double Hard",
+                "dance-double Edit",
+                "dance-single Challenge",
+                "dance-double Challenge"
+            ]
+        );
+        assert!(contents.contains(charts[0].text.trim_end()));
+        assert!(!contents.contains("10000000\n10000000"));
+        assert!(human.iter().all(|h| contents.contains(h.as_str())));
+        assert_eq!(metadata.len(), 2);
+
+        // generating again changes nothing, and -r removes the old edit
+        let (again, _) = write_generated(
+            &contents,
+            metadata.clone(),
+            generate(&contents),
+            false,
+            is_ssc,
+        )
+        .unwrap();
+        assert_eq!(again, contents);
+        let (stripped, metadata) =
+            write_generated(&contents, metadata, generate(&contents), true, is_ssc).unwrap();
+        assert_eq!(
+            summaries(&stripped),
+            vec![
+                "dance-single Hard",
+                "dance-double Hard",
+                "dance-single Challenge",
+                "dance-double Challenge"
+            ]
+        );
+        assert_eq!(metadata.len(), 2);
+        assert!(human.iter().all(|h| stripped.contains(h.as_str())));
     }
+}
 
-    Ok(ret)
+#[test]
+fn test_difficulty_policy() {
+    let orig = "#BPMS:0=120;\n#NOTES:\n     dance-single:\n     a:\n     Hard:\n     9:\n     :\n1000\n0100\n;\n#NOTES:\n     dance-single:\n     b:\n     Challenge:\n     12:\n     :\n0010\n0001\n;\n#NOTES:\n     dance-double:\n     official:\n     Challenge:\n     13:\n     :\n00001000\n;\n";
+    let generate = |difficulties| {
+        generate_charts(
+            orig,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            GeneratorParameters::default(),
+            &ParamCurves::default(),
+            difficulties,
+            None,
+            false,
+        )
+    };
+    assert!(generate(DifficultyPolicy::Same).is_err());
+    let (charts, skipped) = generate(DifficultyPolicy::FillGaps).unwrap();
+    assert_eq!(
+        skipped,
+        vec![
+            Skipped::Style("dance-double".to_owned()),
+            Skipped::Taken("Challenge".to_owned(), "dance-double".to_owned())
+        ]
+    );
+    assert_eq!(
+        skipped[1].to_string(),
+        "skipping Challenge, there already is a dance-double Challenge chart"
+    );
+    assert_eq!(charts.len(), 1);
+    assert_eq!(charts[0].metadata.from_difficulty, "Hard");
+    assert!(!charts[0].metadata.edit);
+    let (charts, _) = generate(DifficultyPolicy::FillGapsOrEdit).unwrap();
+    assert_eq!(charts.len(), 2);
+    assert!(!charts[0].metadata.edit);
+    assert!(charts[1].metadata.edit);
+    assert!(!charts[1].metadata.write_from_difficulty);
+    let (contents, _) = write_generated(orig, Vec::new(), charts, false, false).unwrap();
+    let difficulties: Vec<String> = parse_charts(&contents, false)
+        .unwrap()
+        .into_iter()
+        .map(|c| format!("{} {}", c.style, c.difficulty))
+        .collect();
+    assert_eq!(
+        difficulties,
+        vec![
+            "dance-single Hard",
+            "dance-single Challenge",
+            "dance-double Challenge",
+            "dance-double Hard",
+            "dance-double Edit"
+        ]
+    );
+
+    // edits, however they are spelled, don't take a difficulty
+    let edit = orig.replace("official:\n     Challenge:", "official:\n     edit:");
+    let generate = |contents: &str| {
+        generate_charts(
+            contents,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            GeneratorParameters::default(),
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
+            None,
+            false,
+        )
+    };
+    assert_ne!(edit, orig);
+    assert_eq!(generate(&edit).unwrap().0.len(), 2);
+}
+
+#[test]
+fn test_source_policy() {
+    let chart = |style: &str, description: &str, difficulty: &str, level: i32| {
+        let notes = if style == "pump-single" {
+            "10000\n00001"
+        } else {
+            "1000\n0001"
+        };
+        format!(
+            "#NOTES:\n     {}:\n     {}:\n     {}:\n     {}:\n     :\n{}\n;\n",
+            style, description, difficulty, level, notes
+        )
+    };
+    let orig = [
+        chart("dance-single", "a", "Easy", 3),
+        chart("pump-single", "c", "Easy", 4),
+        chart("pump-single", "d", "Hard", 8),
+        chart("pump-single", "e", "Challenge", 10),
+        chart("dance-single", "b", "Challenge", 12),
+        chart("pump-single", "f", "Edit", 20),
+    ]
+    .concat();
+    let generate = |from_styles: &[Style], sources| {
+        generate_charts_from_styles(
+            &orig,
+            from_styles,
+            sources,
+            Style::ItgDoubles,
+            GeneratorParameters::default(),
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
+            None,
+            false,
+        )
+        .unwrap()
+        .0
+        .into_iter()
+        .map(|c| {
+            let m = c.metadata;
+            format!(
+                "{} {} -> {}",
+                m.from_style.sm_string(),
+                m.from_description,
+                write_difficulty(&m)
+            )
+        })
+        .collect::<Vec<_>>()
+    };
+    let (itg, pump) = (Style::ItgSingles, Style::PumpSingles);
+    assert_eq!(
+        generate(&[itg, pump], SourcePolicy::FirstStyle),
+        vec!["dance-single a -> Easy", "dance-single b -> Challenge"]
+    );
+    assert_eq!(generate(&[pump, itg], SourcePolicy::FirstStyle).len(), 4);
+    assert_eq!(
+        generate(&[Style::PumpDoubles, pump], SourcePolicy::FirstStyle).len(),
+        4
+    );
+    assert_eq!(
+        generate(&[itg, pump], SourcePolicy::SameDifficulty),
+        vec![
+            "dance-single a -> Easy",
+            "pump-single d -> Hard",
+            "dance-single b -> Challenge"
+        ]
+    );
+    assert_eq!(
+        generate(&[itg, pump], SourcePolicy::ClosestMeter),
+        vec![
+            "dance-single a -> Easy",
+            "pump-single d -> Medium",
+            "pump-single e -> Hard",
+            "dance-single b -> Challenge"
+        ]
+    );
+    assert!(generate(&[Style::PumpDoubles], SourcePolicy::ClosestMeter).is_empty());
+}
+
+#[test]
+fn test_regenerate() {
+    for is_ssc in [false, true] {
+        let orig = if is_ssc {
+            "A\n#BPMS:0=120;\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Hard;\n#DESCRIPTION:a;\n#METER:9;\n#NOTES:\n1000\n0100\n0010\n0001\n;\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Challenge;\n#DESCRIPTION:b;\n#METER:12;\n#NOTES:\n1001\n0200\n0310\n;\n"
+        } else {
+            "A\n#BPMS:0=120;\n#NOTES:\n     dance-single:\n     a:\n     Hard:\n     9:\n     :\n1000\n0100\n0010\n0001\n;\n#NOTES:\n     dance-single:\n     b:\n     Challenge:\n     12:\n     :\n1001\n0200\n0310\n;\n"
+        };
+        let charts = generate_charts(
+            orig,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            GeneratorParameters {
+                max_angle: Some(2.0),
+                ..GeneratorParameters::default()
+            },
+            &ParamCurves::default(),
+            DifficultyPolicy::Edit,
+            None,
+            is_ssc,
+        )
+        .unwrap()
+        .0;
+        assert_eq!(charts.len(), 2);
+        let mut contents = orig.to_owned();
+        contents.push('\n');
+        for c in &charts {
+            contents.push_str(&c.text);
+        }
+        let replace = |contents: &str, m: &ChartMetadata| {
+            let regenerated = regenerate_chart(contents, m, is_ssc).unwrap();
+            write_generated(contents, Vec::new(), vec![regenerated], false, is_ssc)
+                .unwrap()
+                .0
+        };
+        for c in &charts {
+            // through JSON, like the metadata file
+            let m: ChartMetadata =
+                serde_json::from_str(&serde_json::to_string(&c.metadata).unwrap()).unwrap();
+            assert_eq!(
+                regenerate_chart(&contents, &m, is_ssc).unwrap().text,
+                c.text
+            );
+            assert_eq!(replace(&contents, &m), contents);
+        }
+
+        // a hand-edited chart is restored
+        let edited = contents.replace(&charts[0].text, &charts[0].text.replace('1', "0"));
+        assert_ne!(edited, contents);
+        assert_eq!(replace(&edited, &charts[0].metadata), contents);
+
+        // and found after StepMania saved the file again, with other tags and spacing
+        let resaved = if is_ssc {
+            let header = "#NOTEDATA:;\n#STEPSTYPE:dance-double;";
+            contents.replacen(
+                header,
+                "#NOTEDATA:;\n#CHARTNAME:;\n#STEPSTYPE:dance-double;\n#CREDIT:;",
+                2,
+            )
+        } else {
+            contents.replace("     dance-double:", "  dance-double: // autogen")
+        };
+        assert_ne!(resaved, contents);
+        let restored = replace(&replace(&resaved, &charts[0].metadata), &charts[1].metadata);
+        assert_eq!(restored, contents);
+
+        // the source chart is gone
+        let m = ChartMetadata {
+            from_level: 10,
+            ..charts[0].metadata.clone()
+        };
+        assert!(regenerate_chart(&contents, &m, is_ssc).is_err());
+    }
 }
 
 #[test]
@@ -457,11 +1977,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     17:\n     :\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     17:\n     :\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTES:\n     dance-single:\n     :\n     Challenge:\n     17:\n     useless:\n0000\n;\n".to_owned();
@@ -470,11 +1991,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-double:\n     AYEAG:\n     Challenge:\n     17:\n     :\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG:\n     Challenge:\n     17:\n     :\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n0000\n;\n".to_owned();
@@ -483,11 +2005,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             Some(&"foo".to_owned()),
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-double:\n     AYEAG - foo - Zaia:\n     Challenge:\n     17:\n     :\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG - foo - Zaia:\n     Challenge:\n     17:\n     :\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Hard:\n     17:\n     useless:\n0000\n;\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n0000\n;\n".to_owned();
@@ -496,11 +2019,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Hard:\n     17:\n     :\n00000000\n;\n#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     17:\n     :\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Hard:\n     17:\n     :\n00000000\n;\n#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     17:\n     :\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Hard:\n     17:\n     useless:\n0000\n;\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n0000\n;\n".to_owned();
@@ -509,7 +2033,8 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgSingles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
@@ -522,11 +2047,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            true,
+            &ParamCurves::default(),
+            DifficultyPolicy::Edit,
             None,
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-double:\n     AYEAG[H] - Zaia:\n     Edit:\n     17:\n     :\n00000000\n;\n#NOTES:\n     dance-double:\n     AYEAG[C] - Zaia:\n     Edit:\n     17:\n     :\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG[H] - Zaia:\n     Edit:\n     17:\n     :\n00000000\n;\n#NOTES:\n     dance-double:\n     AYEAG[C] - Zaia:\n     Edit:\n     17:\n     :\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Hard:\n     17:\n     useless:\n0000\n;\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n0000\n;\n".to_owned();
@@ -535,11 +2061,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgSingles,
             params,
-            true,
+            &ParamCurves::default(),
+            DifficultyPolicy::Edit,
             None,
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-single:\n     AYEAG[H] - Zaia:\n     Edit:\n     17:\n     :\n0000\n;\n#NOTES:\n     dance-single:\n     AYEAG[C] - Zaia:\n     Edit:\n     17:\n     :\n0000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-single:\n     AYEAG[H] - Zaia:\n     Edit:\n     17:\n     :\n0000\n;\n#NOTES:\n     dance-single:\n     AYEAG[C] - Zaia:\n     Edit:\n     17:\n     :\n0000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge;\n     17:\n     useless:\n0000\n;".to_owned();
@@ -548,7 +2075,8 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
@@ -561,7 +2089,8 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
@@ -574,11 +2103,13 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
-        assert!(g.is_err());
+        // charts itggen wrote are replaced instead, see write_generated()
+        assert!(g.is_ok());
     }
     {
         let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n0000\n".to_owned();
@@ -587,7 +2118,8 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
@@ -600,7 +2132,8 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
@@ -613,11 +2146,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             GeneratorParameters::default(),
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-double:\n     AYEAG(F) - Zaia:\n     Challenge:\n     17:\n     :\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG(F) - Zaia:\n     Challenge:\n     17:\n     :\n00000000\n;\n".to_owned())
     }
     {
         let params = GeneratorParameters {
@@ -630,11 +2164,31 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
         assert_eq!(g.unwrap().matches('1').count(), 1);
+
+        // the rest of a jump is dropped, hands still follow the hands policy
+        let orig = "#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     33:\n     useless:\n0110\n1111\n;\n";
+        for (hands, dropped, notes) in [(HandsPolicy::Jumps, 4, 2), (HandsPolicy::Hands, 2, 4)] {
+            let charts = generate_charts(
+                orig,
+                Style::ItgSingles,
+                Style::ItgDoubles,
+                GeneratorParameters { hands, ..params },
+                &ParamCurves::default(),
+                DifficultyPolicy::Same,
+                None,
+                false,
+            )
+            .unwrap()
+            .0;
+            assert_eq!(charts[0].dropped, dropped);
+            assert_eq!(charts[0].text.matches('1').count(), notes);
+        }
     }
     {
         let params = GeneratorParameters::default();
@@ -644,7 +2198,8 @@ fn test_generate() {
             Style::ItgSingles,
             Style::PumpHalfDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
@@ -660,7 +2215,8 @@ fn test_generate() {
             Style::ItgSingles,
             Style::PumpMiddleFour,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
@@ -676,7 +2232,8 @@ fn test_generate() {
             Style::ItgSingles,
             Style::PumpDoublesBrackets,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
@@ -695,11 +2252,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     10:\n     :\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     10:\n     :\n00000000\n;\n".to_owned())
     }
     {
         let params = GeneratorParameters {
@@ -712,11 +2270,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     9:\n     :\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     9:\n     :\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     9:\n     useless:\n0000\n;\nB\n#NOTES:\n     dance-single:\n     AYEAG...:\n     Challenge:\n     10:\n     useless:\n0000\n;\n".to_owned();
@@ -725,11 +2284,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     9:\n     :\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     9:\n     :\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Challenge;\n#DESCRIPTION:wow;\n#METER:13;\n#NOTES:\n0000\n;".to_owned();
@@ -738,11 +2298,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            true,
+            &ParamCurves::default(),
+            DifficultyPolicy::Edit,
             None,
             true,
         );
-        assert_eq!(g, Ok("#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Edit;\n#METER:13;\n#NOTES:\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Edit;\n#METER:13;\n#NOTES:\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Challenge;\n#DESCRIPTION:wow;\n#METER:13;\n#NOTES:\n0000\n,\n0000\n;".to_owned();
@@ -751,11 +2312,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            true,
+            &ParamCurves::default(),
+            DifficultyPolicy::Edit,
             None,
             true,
         );
-        assert_eq!(g, Ok("#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Edit;\n#METER:13;\n#NOTES:\n00000000\n,\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Edit;\n#METER:13;\n#NOTES:\n00000000\n,\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Challenge;\n#DESCRIPTION:wow;\n#METER:13;\n#NOTES:\n0000\n;".to_owned();
@@ -764,11 +2326,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             true,
         );
-        assert_eq!(g, Ok("#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Challenge;\n#METER:13;\n#NOTES:\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Challenge;\n#METER:13;\n#NOTES:\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Challenge;\n#DESCRIPTION:wow;\n#METER:13;\n#NOTES:\n0000\n;#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Challenge;\n#DESCRIPTION:wow;\n#METER:13;\n#NOTES:\n0000\n;".to_owned();
@@ -777,11 +2340,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             true,
         );
-        assert_eq!(g, Ok("#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Challenge;\n#METER:13;\n#NOTES:\n00000000\n;\n#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Challenge;\n#METER:13;\n#NOTES:\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Challenge;\n#METER:13;\n#NOTES:\n00000000\n;\n#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Challenge;\n#METER:13;\n#NOTES:\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Challenge;\n#DESCRIPTION:wow;\n#NOTES:\n0000\n;\n#METER:13;\n".to_owned();
@@ -790,11 +2354,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             true,
         );
-        assert_eq!(g, Ok("#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Challenge;\n#METER:13;\n#NOTES:\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG - wow;\n#DIFFICULTY:Challenge;\n#METER:13;\n#NOTES:\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#DIFFICULTY:Challenge;\n#DESCRIPTION:;\n#NOTES:\n0000\n;\n#METER:13;\n".to_owned();
@@ -803,11 +2368,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             true,
         );
-        assert_eq!(g, Ok("#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG;\n#DIFFICULTY:Challenge;\n#METER:13;\n#NOTES:\n00000000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTEDATA:;\n#STEPSTYPE:dance-double;\n#DESCRIPTION:AYEAG;\n#DIFFICULTY:Challenge;\n#METER:13;\n#NOTES:\n00000000\n;\n".to_owned())
     }
     {
         let orig = "A\n#NOTEDATA:;\n#DIFFICULTY:Challenge;\n#DESCRIPTION:wow;\n#METER:13;\n#NOTES:\n0000\n;".to_owned();
@@ -816,11 +2382,14 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             true,
         );
-        assert_eq!(g, Err("Couldn't parse chart: No STEPSTYPE".to_owned()));
+        assert!(
+            matches!(g, Err(Error::Parse(e)) if e.to_string() == "line 2: missing #STEPSTYPE (chart 1)")
+        );
     }
     {
         let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n0110\n0110\n;\n".to_owned();
@@ -829,11 +2398,12 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
-        assert_eq!(g, Ok("#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     17:\n     :\n00011000\n00011000\n;\n".to_owned()))
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     17:\n     :\n00011000\n00011000\n;\n".to_owned())
     }
     {
         // check that
@@ -843,7 +2413,8 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
-            false,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
@@ -852,30 +2423,366 @@ fn test_generate() {
             Style::ItgSingles,
             Style::ItgDoubles,
             params,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
+            None,
+            false,
+        );
+        assert_eq!(g1.unwrap(), g2.unwrap());
+    }
+    {
+        let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n0200\n1000\n0001\n1000\n0300\n0040\n0030\n;\n".to_owned();
+        let g = generate(
+            &orig,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            params,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
+            None,
+            false,
+        )
+        .unwrap();
+        let rows: Vec<&str> = g.lines().skip(6).take(7).collect();
+        let hold_col = rows[0].find('2').unwrap();
+        assert_eq!(rows[4].find('3'), Some(hold_col));
+        for row in &rows[1..4] {
+            assert_eq!(row.matches('1').count(), 1);
+            assert_ne!(row.find('1'), Some(hold_col));
+        }
+        let roll_col = rows[5].find('4').unwrap();
+        assert_eq!(rows[6].find('3'), Some(roll_col));
+    }
+    {
+        let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n1000\n0001\nM000\n1000\n0M10\n;\n".to_owned();
+        let g = generate(
+            &orig,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            params,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
+            None,
             false,
+        )
+        .unwrap();
+        let rows: Vec<&str> = g.lines().skip(6).take(5).collect();
+        // jack mine stays on the panel the left foot just stepped on
+        assert_eq!(rows[2].find('M'), rows[0].find('1'));
+        assert_eq!(rows[4].matches('M').count(), 1);
+        assert_ne!(rows[4].find('M'), rows[4].find('1'));
+        assert_ne!(rows[4].find('M'), rows[3].find('1'));
+
+        // a tap during a two foot hold doesn't leave a foot held after the holds end
+        let orig = "#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n2002\n0100\n0010\n3003\n1000\n0001\n1000\n1000\n1000\n0001\n0001\n0001\n;\n";
+        for seed in 0..8 {
+            let g = generate(
+                orig,
+                Style::ItgSingles,
+                Style::ItgDoubles,
+                crate::preset::create_params(
+                    Some(seed),
+                    0,
+                    false,
+                    false,
+                    true,
+                    false,
+                    HandsPolicy::Jumps,
+                    None,
+                    None,
+                ),
+                &ParamCurves::default(),
+                DifficultyPolicy::Same,
+                None,
+                false,
+            )
+            .unwrap();
+            let rows: Vec<&str> = g.lines().skip(6).take(12).collect();
+            assert_eq!(rows[1..3], ["00000000", "00000000"], "seed {}", seed);
+            assert_eq!(rows[6..9], [rows[4]; 3], "seed {}", seed);
+            assert_eq!(rows[9..12], [rows[5]; 3], "seed {}", seed);
+        }
+
+        // mines in columns the feet didn't last step in never go where the next step lands
+        let orig = format!(
+            "#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n{};\n",
+            "1000\n0001\n0M00\n0100\n00M0\n0010\n1001\n0MM0\n".repeat(16)
+        );
+        for seed in 0..8 {
+            let g = generate(
+                &orig,
+                Style::ItgSingles,
+                Style::ItgDoubles,
+                GeneratorParameters {
+                    seed: Some(seed),
+                    ..params
+                },
+                &ParamCurves::default(),
+                DifficultyPolicy::Same,
+                None,
+                false,
+            )
+            .unwrap();
+            let rows: Vec<&[u8]> = g
+                .lines()
+                .filter(|l| l.len() == 8 && l.starts_with(['0', '1', 'M']))
+                .map(|l| l.as_bytes())
+                .collect();
+            assert_eq!(rows.len(), 128);
+            for (r, row) in rows.iter().enumerate() {
+                let Some(next) = rows[r + 1..].iter().find(|n| n.contains(&b'1')) else {
+                    continue;
+                };
+                for (i, b) in row.iter().enumerate() {
+                    assert!(*b != b'M' || next[i] != b'1', "seed {} row {}", seed, r);
+                }
+            }
+        }
+    }
+    {
+        // 4ths at 60 bpm leave time to move across the pad, 4ths at 240 bpm don't
+        let params = GeneratorParameters {
+            timed_max_horizontal_dist_between_steps: Some((0.0, 2.0)),
+            ..params
+        };
+        for (bpm, max_dist) in [(60, 4.0), (240, 1.0)] {
+            let orig = format!(
+                "#OFFSET:0;\n#BPMS:0={};\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n1000\n0001\n1000\n0001\n;\n",
+                bpm
+            );
+            let g = generate(
+                &orig,
+                Style::ItgSingles,
+                Style::ItgDoubles,
+                params,
+                &ParamCurves::default(),
+                DifficultyPolicy::Same,
+                None,
+                false,
+            )
+            .unwrap();
+            let rows: Vec<f32> = g
+                .lines()
+                .skip(6)
+                .take(4)
+                .map(|r| Style::ItgDoubles.coord(r.find('1').unwrap() as i8).0)
+                .collect();
+            assert!((rows[2] - rows[0]).abs() <= max_dist);
+            assert!((rows[3] - rows[1]).abs() <= max_dist);
+        }
+        let orig = "#BPMS:0=abc;\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n1000\n;\n";
+        let g = generate(
+            orig,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            params,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
             None,
             false,
         );
+        assert!(g.is_err());
+    }
+    {
+        let params = GeneratorParameters {
+            beam_width: Some(4),
+            ..params
+        };
+        let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n1000\n0100\n0010\n,\n0001\n0200\n0300\n;\n".to_owned();
+        let g1 = generate(
+            &orig,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            params,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
+            None,
+            false,
+        )
+        .unwrap();
+        let g2 = generate(
+            &orig,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            params,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
+            None,
+            false,
+        )
+        .unwrap();
         assert_eq!(g1, g2);
+        let rows: Vec<&str> = g1.lines().skip(6).collect();
+        assert_eq!(rows.len(), 8);
+        assert_eq!(rows[3], ",");
+        assert_eq!(rows[7], ";");
+        assert_eq!(rows[6].find('3'), rows[5].find('2'));
+    }
+    {
+        // pins the output for a fixed input, so that charts don't change between builds
+        let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n1000\n0100\n0010\n0001\n,\n1001\n0110\n2000\n3100\n;\n".to_owned();
+        let g = generate(
+            &orig,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            params,
+            &ParamCurves::default(),
+            DifficultyPolicy::Same,
+            None,
+            false,
+        );
+        assert_eq!(g.unwrap(), "#NOTES:\n     dance-double:\n     AYEAG - Zaia:\n     Challenge:\n     17:\n     :\n00010000\n00001000\n00100000\n00000010\n,\n10000010\n00001010\n00000200\n01000300\n;\n".to_owned());
+    }
+    {
+        // parameters follow the level of each chart
+        let orig = "A\n#NOTES:\n     dance-single:\n     A:\n     Easy:\n     3:\n     useless:\n1000\n;\n#NOTES:\n     dance-single:\n     B:\n     Challenge:\n     12:\n     useless:\n1000\n;\n".to_owned();
+        let (_, curves) = crate::preset::parse(
+            "[curves]\ndisallow_footswitch = { steps = [[10, false]] }\n",
+            false,
+        )
+        .unwrap();
+        let g = generate(
+            &orig,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            params,
+            &curves,
+            DifficultyPolicy::Same,
+            None,
+            false,
+        )
+        .unwrap();
+        assert!(g.contains("AYEAG - A:"));
+        assert!(g.contains("AYEAG(F) - B:"));
+    }
+    {
+        // no column is ever far enough from the other foot, so the limit is relaxed
+        let params = GeneratorParameters {
+            max_dist_between_feet: Some(0.5),
+            ..params
+        };
+        let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n1000\n0100\n0010\n;\n".to_owned();
+        for beam_width in [None, Some(4)] {
+            let params = GeneratorParameters {
+                beam_width,
+                ..params
+            };
+            let g = generate(
+                &orig,
+                Style::ItgSingles,
+                Style::ItgDoubles,
+                params,
+                &ParamCurves::default(),
+                DifficultyPolicy::Same,
+                None,
+                false,
+            );
+            assert!(g.is_ok());
+        }
+        let chart = parse_charts(&orig, false).unwrap().remove(0);
+        let generated =
+            generate_notes(&chart, &TimingData::default(), Style::ItgDoubles, params).unwrap();
+        assert!(
+            generated
+                .relaxations
+                .iter()
+                .all(|(_, rule)| *rule == Rule::MaxDistBetweenFeet)
+        );
+        assert!(!generated.relaxations.is_empty());
+        // lines 8 to 10 of the file
+        assert!(
+            generated
+                .relaxations
+                .iter()
+                .all(|(line, _)| (8..=10).contains(line))
+        );
+    }
+    for (hands, notes) in [
+        (HandsPolicy::Jumps, 2),
+        (HandsPolicy::Brackets, 4),
+        (HandsPolicy::Hands, 4),
+    ] {
+        let params = GeneratorParameters { hands, ..params };
+        let orig = "A\n#NOTES:\n     dance-single:\n     Zaia:\n     Challenge:\n     17:\n     useless:\n1001\n1111\n;\n".to_owned();
+        let g = generate(
+            &orig,
+            Style::ItgSingles,
+            Style::ItgSingles,
+            params,
+            &ParamCurves::default(),
+            DifficultyPolicy::Edit,
+            None,
+            false,
+        )
+        .unwrap();
+        let rows: Vec<&str> = g.lines().skip(6).take(2).collect();
+        assert_eq!(rows[0].matches('1').count(), 2);
+        assert_eq!(rows[1].matches('1').count(), notes);
+    }
+    {
+        // STEPSTYPEs are matched like StepMania matches them
+        let orig = "A\n#NOTES:\n     Dance-Single :\n     Zaia:\n     Challenge:\n     17:\n     :\n1000\n;\n#NOTES:\n     DANCE-DOUBLE:\n     :\n     Hard:\n     9:\n     :\n10000000\n;\n";
+        let g = generate(
+            orig,
+            Style::ItgSingles,
+            Style::ItgDoubles,
+            params,
+            &ParamCurves::default(),
+            DifficultyPolicy::FillGaps,
+            None,
+            false,
+        )
+        .unwrap();
+        assert!(g.contains("AYEAG - Zaia:\n     Challenge:"));
+        assert!(matches!(
+            generate(
+                orig,
+                Style::ItgSingles,
+                Style::ItgDoubles,
+                params,
+                &ParamCurves::default(),
+                DifficultyPolicy::Same,
+                None,
+                false,
+            ),
+            Err(Error::Generate(_))
+        ));
+    }
+}
+
+// Returns idx moved past the rest of its line if that is only whitespace.
+fn skip_rest_of_line(contents: &str, idx: usize) -> usize {
+    let rest = &contents[idx..];
+    let line_len = rest.find('\n').map_or(rest.len(), |i| i + 1);
+    if rest[..line_len].trim().is_empty() {
+        idx + line_len
+    } else {
+        idx
     }
 }
 
+// Removes the charts itggen wrote, going by their description, and leaves everything else as it
+// is. Unlike parse_charts(), this works on charts that are missing fields.
 pub fn remove_existing_autogen(contents: &str, is_ssc: bool) -> String {
-    let mut res = String::new();
-    let separator = if is_ssc { "#NOTEDATA:" } else { "#NOTES:" };
-    let mut first = true;
-    for split in contents.split(separator) {
-        if first {
-            res.push_str(split);
-            first = false;
-            continue;
-        }
-        if !split.contains("AYEAG") {
-            res.push_str(separator);
-            res.push_str(split);
+    let values = msd::parse(contents);
+    let mut ret = String::new();
+    let mut at = 0;
+    for chart in chart_values(&values, is_ssc) {
+        let description = if is_ssc {
+            chart
+                .iter()
+                .find(|v| v.key() == "DESCRIPTION")
+                .map_or("", |v| v.param(1))
+        } else {
+            chart[0].param(2)
+        };
+        if written_by_itggen(description) {
+            ret.push_str(&contents[at..chart[0].start]);
+            at = skip_rest_of_line(contents, chart[chart.len() - 1].end);
         }
     }
-    res
+    ret.push_str(&contents[at..]);
+    ret
 }
 
 #[test]
@@ -934,4 +2841,12 @@ fn test_remove_existing_autogen() {
             "ABC\nDEF\n#NOTEDATA:\n#NOTES:;\n#DESCRIPTION:AYEnoAG - 1;\n".to_owned()
         );
     }
+    {
+        // only the description counts
+        let orig = "A\n#NOTES:\nasdf:\nnot AYEAG:\n;\n#NOTES:\nasdf:\na:\n// AYEAG\n;\n#NOTES:\nasdf:\nAYEAG\\:1:\n;  \n// b\n".to_owned();
+        assert_eq!(
+            remove_existing_autogen(&orig, false),
+            "A\n#NOTES:\nasdf:\nnot AYEAG:\n;\n#NOTES:\nasdf:\na:\n// AYEAG\n;\n// b\n".to_owned()
+        );
+    }
 }
@@ -1,5 +1,7 @@
 use crate::coord::Coord;
 use crate::foot::Foot;
+use crate::layout::{self, LayoutId};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::str::FromStr;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -17,6 +19,8 @@ pub enum Style {
     HorizonDoubles,
     HorizonTriples,
     Quads,
+    // a pad loaded from a layout file, see layout.rs
+    Custom(LayoutId),
 }
 
 #[derive(Debug)]
@@ -26,32 +30,72 @@ impl FromStr for Style {
     type Err = StyleParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "itg-singles" => Ok(Style::ItgSingles),
-            "itg-doubles" => Ok(Style::ItgDoubles),
-            "itg-triples" => Ok(Style::ItgTriples),
-            "pump-singles" => Ok(Style::PumpSingles),
-            "pump-doubles" => Ok(Style::PumpDoubles),
-            "pump-triples" => Ok(Style::PumpTriples),
-            "pump-halfdoubles" => Ok(Style::PumpHalfDoubles),
-            "pump-doubles-brackets" => Ok(Style::PumpDoublesBrackets),
-            "pump-middlefour" => Ok(Style::PumpMiddleFour),
-            "horizon-singles" => Ok(Style::HorizonSingles),
-            "horizon-doubles" => Ok(Style::HorizonDoubles),
-            "horizon-triples" => Ok(Style::HorizonTriples),
-            "quads" => Ok(Style::Quads),
-            _ => Err(StyleParseError(s.to_owned())),
-        }
+        Style::ALL
+            .into_iter()
+            .find(|style| style.name() == s)
+            .or_else(|| layout::find(|l| l.name == s))
+            .ok_or_else(|| StyleParseError(s.to_owned()))
+    }
+}
+
+// Serialized as its name.
+impl Serialize for Style {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
     }
 }
 
-impl ToString for StyleParseError {
-    fn to_string(&self) -> String {
-        format!("could not parse style '{}'", self.0)
+impl<'de> Deserialize<'de> for Style {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(serde::de::Error::custom::<StyleParseError>)
+    }
+}
+
+impl std::fmt::Display for StyleParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not parse style '{}'", self.0)
     }
 }
 
 impl Style {
+    pub const ALL: [Style; 13] = [
+        Style::ItgSingles,
+        Style::ItgDoubles,
+        Style::ItgTriples,
+        Style::PumpSingles,
+        Style::PumpDoubles,
+        Style::PumpTriples,
+        Style::PumpHalfDoubles,
+        Style::PumpDoublesBrackets,
+        Style::PumpMiddleFour,
+        Style::HorizonSingles,
+        Style::HorizonDoubles,
+        Style::HorizonTriples,
+        Style::Quads,
+    ];
+
+    // The name parsed by from_str().
+    pub fn name(&self) -> &'static str {
+        match self {
+            Style::ItgSingles => "itg-singles",
+            Style::ItgDoubles => "itg-doubles",
+            Style::ItgTriples => "itg-triples",
+            Style::PumpSingles => "pump-singles",
+            Style::PumpDoubles => "pump-doubles",
+            Style::PumpTriples => "pump-triples",
+            Style::PumpHalfDoubles => "pump-halfdoubles",
+            Style::PumpDoublesBrackets => "pump-doubles-brackets",
+            Style::PumpMiddleFour => "pump-middlefour",
+            Style::HorizonSingles => "horizon-singles",
+            Style::HorizonDoubles => "horizon-doubles",
+            Style::HorizonTriples => "horizon-triples",
+            Style::Quads => "quads",
+            Style::Custom(i) => &layout::get(*i).name,
+        }
+    }
+
     pub fn num_cols(&self) -> i8 {
         match self {
             Style::ItgSingles => 4,
@@ -67,6 +111,7 @@ impl Style {
             Style::HorizonDoubles => 18,
             Style::HorizonTriples => 27,
             Style::Quads => 18,
+            Style::Custom(i) => layout::get(*i).num_cols(),
         }
     }
 
@@ -74,11 +119,12 @@ impl Style {
         match self {
             Style::PumpHalfDoubles => 2,
             Style::PumpMiddleFour => 3,
+            Style::Custom(i) => layout::get(*i).padding,
             _ => 0,
         }
     }
 
-    pub fn sm_string(&self) -> &str {
+    pub fn sm_string(&self) -> &'static str {
         match self {
             Style::ItgSingles => "dance-single",
             Style::ItgDoubles => "dance-double",
@@ -93,9 +139,26 @@ impl Style {
             Style::HorizonDoubles => "horizon-double",
             Style::HorizonTriples => "horizon-triple",
             Style::Quads => "quads",
+            Style::Custom(i) => &layout::get(*i).stepstype,
         }
     }
 
+    // Whether s is the STEPSTYPE of charts of this style, which like in StepMania ignores case and
+    // surrounding whitespace.
+    pub fn is_sm_string(&self, s: &str) -> bool {
+        self.sm_string().eq_ignore_ascii_case(s.trim())
+    }
+
+    // Returns the style of charts with the given STEPSTYPE, e.g. "dance-single". Charts written
+    // as "pump-double" are PumpDoubles, and layouts are only used for STEPSTYPEs no built-in
+    // style has.
+    pub fn from_sm_string(s: &str) -> Option<Style> {
+        Style::ALL
+            .into_iter()
+            .find(|style| style.is_sm_string(s))
+            .or_else(|| layout::find(|l| l.stepstype.eq_ignore_ascii_case(s.trim())))
+    }
+
     pub fn init_col(&self, foot: Foot) -> i8 {
         match self {
             Style::ItgSingles => match foot {
@@ -150,6 +213,10 @@ impl Style {
                 Foot::Left => 9,
                 Foot::Right => 10,
             },
+            Style::Custom(i) => match foot {
+                Foot::Left => layout::get(*i).start.0,
+                Foot::Right => layout::get(*i).start.1,
+            },
         }
     }
 
@@ -166,6 +233,7 @@ impl Style {
             Style::PumpMiddleFour => 1.0,
             Style::PumpDoublesBrackets => 4.0,
             Style::Quads => 11.0,
+            Style::Custom(i) => layout::get(*i).max_x_coord(),
         }
     }
 
@@ -365,9 +433,20 @@ impl Style {
                 17 => Coord(11.0, 1.0),
                 _ => panic!(),
             },
+            Style::Custom(i) => layout::get(*i).coord(col),
         }
     }
 
+    // Whether one foot can hold down both columns at once.
+    pub fn can_bracket(&self, col1: i8, col2: i8) -> bool {
+        if let Style::Custom(i) = self
+            && let Some(ret) = layout::get(*i).can_bracket(col1, col2)
+        {
+            return ret;
+        }
+        col1 != col2 && self.coord(col1).dist(self.coord(col2)) < 1.5
+    }
+
     pub fn sm_cols_for_col(&self, col: i8) -> Vec<i8> {
         match self {
             Style::PumpDoublesBrackets => match col {
@@ -383,9 +462,50 @@ impl Style {
                 9 => vec![9, 7],
                 _ => panic!(),
             },
+            Style::Custom(i) => layout::get(*i).sm_cols_for_col(col),
             _ => {
                 vec![col]
             }
         }
     }
 }
+
+#[test]
+fn test_can_bracket() {
+    assert!(Style::ItgSingles.can_bracket(0, 1));
+    assert!(Style::ItgSingles.can_bracket(2, 3));
+    assert!(!Style::ItgSingles.can_bracket(0, 3));
+    assert!(!Style::ItgSingles.can_bracket(1, 2));
+    assert!(!Style::ItgSingles.can_bracket(1, 1));
+    assert!(Style::ItgDoubles.can_bracket(3, 4));
+    assert!(Style::PumpSingles.can_bracket(0, 2));
+    assert!(!Style::PumpSingles.can_bracket(0, 1));
+}
+
+#[test]
+fn test_name() {
+    for style in Style::ALL {
+        assert_eq!(style.name().parse::<Style>().unwrap(), style);
+    }
+}
+
+#[test]
+fn test_from_sm_string() {
+    for style in Style::ALL {
+        assert_eq!(
+            Style::from_sm_string(style.sm_string())
+                .unwrap()
+                .sm_string(),
+            style.sm_string()
+        );
+    }
+    assert_eq!(
+        Style::from_sm_string("pump-double"),
+        Some(Style::PumpDoubles)
+    );
+    assert_eq!(
+        Style::from_sm_string(" Dance-Single "),
+        Some(Style::ItgSingles)
+    );
+    assert_eq!(Style::from_sm_string("kb7-single"), None);
+}
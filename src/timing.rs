@@ -0,0 +1,180 @@
+use crate::error::{ParseError, ParseErrorKind};
+use crate::msd;
+
+// Song timing from the #OFFSET, #BPMS, #STOPS, #DELAYS and #WARPS tags.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TimingData {
+    pub offset: f32,
+    // (beat, bpm)
+    pub bpms: Vec<(f32, f32)>,
+    // (beat, seconds)
+    pub stops: Vec<(f32, f32)>,
+    // (beat, seconds)
+    pub delays: Vec<(f32, f32)>,
+    // (beat, length in beats)
+    pub warps: Vec<(f32, f32)>,
+}
+
+fn parse_pairs(value: &str) -> Result<Vec<(f32, f32)>, String> {
+    let mut ret = Vec::new();
+    for pair in value.split(',') {
+        let pair: String = pair.chars().filter(|c| !c.is_whitespace()).collect();
+        if pair.is_empty() {
+            continue;
+        }
+        let (a, b) = pair
+            .split_once('=')
+            .ok_or(format!("expected 'beat=value', got '{}'", pair))?;
+        let a = a
+            .parse::<f32>()
+            .map_err(|e| format!("Couldn't parse '{}': {}", a, e))?;
+        let b = b
+            .parse::<f32>()
+            .map_err(|e| format!("Couldn't parse '{}': {}", b, e))?;
+        ret.push((a, b));
+    }
+    ret.sort_by(|x, y| x.0.total_cmp(&y.0));
+    Ok(ret)
+}
+
+impl TimingData {
+    // Reads the timing tags out of values.
+    pub fn from_values<'a>(
+        values: impl IntoIterator<Item = &'a msd::Value>,
+    ) -> Result<Self, ParseError> {
+        let mut ret = Self::default();
+        for value in values {
+            ret.set_tag(&value.key(), value.param(1))
+                .map_err(|e| ParseError::new(ParseErrorKind::BadTiming(e), value.line()))?;
+        }
+        Ok(ret)
+    }
+
+    // Sets the timing value for key, ignoring non-timing keys.
+    pub fn set_tag(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "OFFSET" if value.trim().is_empty() => self.offset = 0.0,
+            "OFFSET" => {
+                self.offset = value
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|e| format!("Couldn't parse OFFSET: {}", e))?
+            }
+            "BPMS" => self.bpms = parse_pairs(value)?,
+            "STOPS" | "FREEZES" => self.stops = parse_pairs(value)?,
+            "DELAYS" => self.delays = parse_pairs(value)?,
+            "WARPS" => self.warps = parse_pairs(value)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn has_bpms(&self) -> bool {
+        !self.bpms.is_empty()
+    }
+
+    fn bpm_at(&self, beat: f32) -> f32 {
+        let mut ret = self.bpms.first().map(|(_, bpm)| *bpm).unwrap_or(0.0);
+        for (b, bpm) in &self.bpms {
+            if *b > beat {
+                break;
+            }
+            ret = *bpm;
+        }
+        ret
+    }
+
+    fn is_warped(&self, beat: f32) -> bool {
+        self.warps
+            .iter()
+            .any(|(b, len)| beat >= *b && beat < b + len)
+    }
+
+    // Returns when beat happens, in seconds since the start of the music.
+    pub fn beat_to_seconds(&self, beat: f32) -> f32 {
+        let mut points = vec![0.0, beat];
+        for (b, _) in &self.bpms {
+            points.push(*b);
+        }
+        for (b, len) in &self.warps {
+            points.push(*b);
+            points.push(b + len);
+        }
+        points.retain(|b| *b >= 0.0 && *b <= beat);
+        points.sort_by(|a, b| a.total_cmp(b));
+        points.dedup();
+
+        let mut ret = -self.offset;
+        for w in points.windows(2) {
+            let mid = (w[0] + w[1]) / 2.0;
+            if self.is_warped(mid) {
+                continue;
+            }
+            // negative bpms are old-style warps
+            let bpm = self.bpm_at(mid);
+            if bpm > 0.0 {
+                ret += (w[1] - w[0]) * 60.0 / bpm;
+            }
+        }
+        // notes on a stop are hit before it, notes on a delay after it
+        for (b, secs) in &self.stops {
+            if *b < beat && !self.is_warped(*b) {
+                ret += secs;
+            }
+        }
+        for (b, secs) in &self.delays {
+            if *b <= beat && !self.is_warped(*b) {
+                ret += secs;
+            }
+        }
+        ret
+    }
+}
+
+#[test]
+fn test_parse() {
+    let parse = |s: &str| TimingData::from_values(&msd::parse(s));
+    let t = parse(
+        "#TITLE:a;\n#OFFSET:-0.5;\n#BPMS:0.000=120.000\n,4.000=240.000;\n#STOPS:;\n#DELAYS:2=0.5;\n#WARPS:8.0=4.0;\n",
+    )
+    .unwrap();
+    assert_eq!(
+        t,
+        TimingData {
+            offset: -0.5,
+            bpms: vec![(0.0, 120.0), (4.0, 240.0)],
+            stops: vec![],
+            delays: vec![(2.0, 0.5)],
+            warps: vec![(8.0, 4.0)],
+        }
+    );
+    assert!(parse("#BPMS:0=abc;").is_err());
+    assert!(parse("#OFFSET:x;").is_err());
+    assert_eq!(
+        parse("#TITLE:a;\n#STOPS:1;").map_err(|e| e.line),
+        Err(Some(2))
+    );
+    assert_eq!(parse("#OFFSET:;"), Ok(TimingData::default()));
+}
+
+#[test]
+fn test_beat_to_seconds() {
+    use approx::assert_relative_eq;
+    let t = TimingData {
+        offset: 0.5,
+        bpms: vec![(0.0, 120.0), (4.0, 240.0)],
+        stops: vec![(5.0, 1.0)],
+        delays: vec![(6.0, 2.0)],
+        warps: vec![(8.0, 4.0)],
+    };
+    assert_relative_eq!(t.beat_to_seconds(0.0), -0.5);
+    assert_relative_eq!(t.beat_to_seconds(2.0), 0.5);
+    assert_relative_eq!(t.beat_to_seconds(4.0), 1.5);
+    assert_relative_eq!(t.beat_to_seconds(5.0), 1.75);
+    assert_relative_eq!(t.beat_to_seconds(5.5), 2.875);
+    assert_relative_eq!(t.beat_to_seconds(6.0), 5.0);
+    assert_relative_eq!(t.beat_to_seconds(8.0), 5.5);
+    assert_relative_eq!(t.beat_to_seconds(10.0), 5.5);
+    assert_relative_eq!(t.beat_to_seconds(12.0), 5.5);
+    assert_relative_eq!(t.beat_to_seconds(13.0), 5.75);
+}